@@ -0,0 +1,47 @@
+//! alarm -- Waiting for a wall time.
+//!
+//! User progs are blocked with [Reason::Alarm] until the wall time of the rtc is reached.
+//! The rtc alarm is set to the earliest wall time any user prog is waiting for.
+
+use crate::{
+    hardware::rtc,
+    scheduler::{self, Reason},
+    sys_call::sys_yield,
+};
+
+/// Blocks the current user prog until the wall time in nanoseconds since the Unix epoch.
+///
+/// Returns [None] if the user prog is blocked. The return value is written on wake.
+pub fn wait(time: u64) -> Option<usize> {
+    let cur = scheduler::cur();
+    cur.increment_mepc();
+    if time <= rtc::now() {
+        return Some(0);
+    }
+    cur.set_blocked(Reason::Alarm(time));
+    set_next_alarm();
+    sys_yield();
+    None
+}
+
+/// Wakes the user progs whose wall time is reached. Called after an rtc interrupt.
+pub fn wake_expired() {
+    let now = rtc::now();
+    while let Some((prog, _)) =
+        scheduler::find_blocked_by(|reason| matches!(reason, Reason::Alarm(time) if time <= now))
+    {
+        prog.set_rdy_with_ret(0);
+    }
+    set_next_alarm();
+}
+
+fn set_next_alarm() {
+    let next = scheduler::min_blocked_by(|reason| match reason {
+        Reason::Alarm(time) => Some(time),
+        _ => None,
+    });
+    match next {
+        Some(time) => rtc::set_alarm(time),
+        None => rtc::clear_alarm(),
+    }
+}
@@ -3,5 +3,9 @@ use core::arch::global_asm;
 global_asm!(include_str!("asm/boot.S"));
 global_asm!(include_str!("asm/exception.S"));
 extern "C" {
-    pub fn exception();
+    /// The vectored trap table, exceptions and interrupts without an own entry use the first entry.
+    pub fn trap_vector();
+    /// Ends the trap handling of the hart, restores the registers from the stack and returns to
+    /// the user prog.
+    pub fn trap_return(sp: usize) -> !;
 }
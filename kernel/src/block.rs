@@ -0,0 +1,130 @@
+//! block -- Block devices addressed in sectors.
+//!
+//! Requests are asynchronous. A driver finishes them in its interrupt handler and wakes the
+//! user progs waiting with [Reason::Block]. The kernel itself waits by polling,
+//! as interrupts are disabled while handling a trap.
+
+use riscv_utils::{to_ret, SysCallError};
+
+use crate::{
+    hardware::virtio_blk,
+    scheduler::{self, Reason},
+    sys_call::{self, sys_yield},
+};
+
+pub const SECTOR_SIZE: usize = riscv_utils::SECTOR_SIZE;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Op {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+    /// The sector is beyond the capacity of the device.
+    OutOfRange,
+    /// No free request slot is available.
+    Busy,
+    /// The device failed to process the request.
+    Io,
+    /// The request id does not belong to a submitted request.
+    InvalidRequest,
+}
+impl From<Error> for SysCallError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::OutOfRange | Error::InvalidRequest => SysCallError::InvalidArgument,
+            Error::Busy => SysCallError::Again,
+            Error::Io => SysCallError::Io,
+        }
+    }
+}
+
+pub trait BlockDevice: Sync {
+    /// Returns the number of sectors.
+    fn capacity(&self) -> u64;
+    /// Starts a request on the sector. Returns the request id.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must hold [SECTOR_SIZE] bytes and stay valid until the request is finished.
+    unsafe fn submit(&self, op: Op, sector: u64, buf: *mut u8) -> Result<usize, Error>;
+    /// Returns the result of a finished request and releases the request id.
+    /// Returns [None] while the request is pending.
+    fn poll(&self, id: usize) -> Option<Result<(), Error>>;
+    /// Waits for the request to finish by polling.
+    fn wait(&self, id: usize) -> Result<(), Error> {
+        loop {
+            if let Some(result) = self.poll(id) {
+                return result;
+            }
+            core::hint::spin_loop();
+        }
+    }
+    /// Reads the sector and waits for the request to finish.
+    fn read(&self, sector: u64, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), Error> {
+        let id = unsafe { self.submit(Op::Read, sector, buf.as_mut_ptr())? };
+        self.wait(id)
+    }
+    /// Writes the sector and waits for the request to finish.
+    fn write(&self, sector: u64, buf: &[u8; SECTOR_SIZE]) -> Result<(), Error> {
+        let id = unsafe { self.submit(Op::Write, sector, buf.as_ptr() as *mut u8)? };
+        self.wait(id)
+    }
+}
+
+/// Returns the block device if one was found while probing.
+pub fn device() -> Option<&'static dyn BlockDevice> {
+    virtio_blk::device()
+}
+
+/// Starts a request on the sector for the current user prog and blocks it until the request is finished.
+///
+/// Returns [None] if the user prog is blocked. The return value is written on wake.
+pub fn sys_request(op: Op, sector: u64, buf: *mut u8) -> Option<usize> {
+    let cur = scheduler::cur();
+    cur.increment_mepc();
+    let Some(device) = device() else {
+        return Some(to_ret(Err(SysCallError::NotSupported)));
+    };
+    // The device writes to the buffer directly, the pmp does not protect the kernel.
+    if let Err(err) = sys_call::check_user_memory(buf as usize, SECTOR_SIZE) {
+        return Some(to_ret(Err(err)));
+    }
+    match unsafe { device.submit(op, sector, buf) } {
+        Ok(id) => {
+            cur.set_blocked(Reason::Block(id));
+            // The request may have finished on another hart before the user prog was blocked,
+            // [wake] did not find it then.
+            match device.poll(id) {
+                Some(Err(Error::InvalidRequest)) | None => {
+                    sys_yield();
+                    None
+                }
+                Some(result) => {
+                    cur.set_rdy();
+                    Some(to_sys_ret(result))
+                }
+            }
+        }
+        Err(err) => Some(to_ret(Err(err.into()))),
+    }
+}
+
+/// Wakes the user prog waiting for the request if the request is finished.
+/// Called by the driver after an interrupt. Requests of exited user progs are released as well,
+/// the reaper waits for them.
+pub fn wake(device: &dyn BlockDevice, id: usize) {
+    if let Some(prog) = scheduler::find_blocked(Reason::Block(id)) {
+        match device.poll(id) {
+            // The user prog took the result itself after blocking, see [sys_request].
+            Some(Err(Error::InvalidRequest)) | None => {}
+            Some(result) => prog.set_rdy_with_ret(to_sys_ret(result)),
+        }
+    }
+}
+
+fn to_sys_ret(result: Result<(), Error>) -> usize {
+    to_ret(result.map(|_| 0).map_err(SysCallError::from))
+}
@@ -0,0 +1,46 @@
+//! deferred -- Work deferred from interrupt handlers (bottom halves).
+//!
+//! Interrupt handlers only acknowledge their device and queue the remaining work, e.g. waking
+//! user progs. The queued work runs with interrupts enabled before the kernel returns to a user
+//! prog, so the time spent with interrupts disabled stays short.
+
+use crate::hardware::sync::Protected;
+
+const MAX_WORK: usize = 16;
+
+type Work = Option<fn()>;
+
+/// Queued work in FIFO order.
+static QUEUE: Protected<[Work; MAX_WORK]> = Protected::new([None; MAX_WORK]);
+
+/// Queues the work unless it is already queued.
+pub fn queue(work: fn()) {
+    let mut queue = QUEUE.lock();
+    let mut free = None;
+    for slot in queue.iter_mut().rev() {
+        match slot {
+            Some(queued) if core::ptr::fn_addr_eq(*queued, work) => return,
+            Some(_) => {}
+            None => free = Some(slot),
+        }
+    }
+    *free.expect("The deferred work queue is full") = Some(work);
+}
+
+/// Runs the queued work until the queue is empty.
+pub fn run() {
+    loop {
+        let mut queue = QUEUE.lock();
+        let Some(work) = queue[0].take() else {
+            return;
+        };
+        queue.rotate_left(1);
+        // The work may queue further work.
+        queue.unlock();
+        work();
+    }
+}
+
+pub fn is_empty() -> bool {
+    QUEUE.lock()[0].is_none()
+}
@@ -0,0 +1,80 @@
+//! dev -- Device nodes mounted at `/dev`.
+
+use riscv_utils::SysCallError;
+
+use crate::{
+    fd::Io,
+    hardware::uart,
+    scheduler::{Prog, Reason},
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Device {
+    /// The UART. Only one user prog can hold it for reading at a time.
+    Uart0,
+}
+impl Device {
+    const ALL: [(&'static [u8], Device); 1] = [(b"uart0", Device::Uart0)];
+
+    /// Called for every open of the device node.
+    pub fn open(&self, prog: Prog) -> Result<(), SysCallError> {
+        match self {
+            Device::Uart0 => match uart::open(prog) {
+                true => Ok(()),
+                false => Err(SysCallError::NotPermitted),
+            },
+        }
+    }
+    /// Called when the last file descriptor of the user prog referencing the device is closed.
+    pub fn release(&self, prog: Prog) {
+        match self {
+            Device::Uart0 => {
+                uart::close(prog);
+            }
+        }
+    }
+    pub fn read(&self, prog: Prog, buf: &mut [u8]) -> Result<Io, SysCallError> {
+        match self {
+            Device::Uart0 => read_uart(prog, buf),
+        }
+    }
+    pub fn write(&self, buf: &[u8]) -> Result<Io, SysCallError> {
+        match self {
+            Device::Uart0 => {
+                for byte in buf {
+                    uart::print_char(*byte as char);
+                }
+                Ok(Io::Done(buf.len()))
+            }
+        }
+    }
+}
+
+/// Returns the device at the path relative to `/dev`.
+pub fn find(path: &[u8]) -> Option<Device> {
+    let name = path.strip_prefix(b"/").unwrap_or(path);
+    Device::ALL
+        .iter()
+        .find(|(device_name, _)| *device_name == name)
+        .map(|(_, device)| *device)
+}
+
+/// Returns the name of the device at the index. Returns [None] past the last device.
+pub fn name(idx: usize) -> Option<&'static [u8]> {
+    Device::ALL.get(idx).map(|(name, _)| *name)
+}
+
+/// Reads the received chars. Requires the user prog to hold the UART.
+pub fn read_uart(prog: Prog, buf: &mut [u8]) -> Result<Io, SysCallError> {
+    if !uart::is_open(prog) {
+        return Err(SysCallError::NotPermitted);
+    }
+    for (count, byte) in buf.iter_mut().enumerate() {
+        match uart::get_char() {
+            Some(char) => *byte = char as u8,
+            None if count == 0 => return Ok(Io::Blocked(Reason::Uart)),
+            None => return Ok(Io::Done(count)),
+        }
+    }
+    Ok(Io::Done(buf.len()))
+}
@@ -0,0 +1,147 @@
+//! elf -- Loader for statically linked ELF64 program images.
+//!
+//! [More Info](https://refspecs.linuxfoundation.org/elf/gabi4+/ch4.eheader.html)
+
+use core::ops::Range;
+
+const MAGIC: &[u8] = b"\x7fELF";
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const MACHINE_RISCV: u16 = 0xf3;
+const PT_LOAD: u32 = 1;
+const PT_TLS: u32 = 7;
+/// Alignment of the stack pointer, TLS blocks are placed on the stack.
+const STACK_ALIGN: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+    /// The image is no RISC-V ELF64 image or truncated.
+    InvalidImage,
+    /// A segment or the entry point is outside of the memory of the user prog.
+    OutOfRange,
+}
+
+/// A loaded program image.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Image {
+    pub entry: usize,
+    pub tls: Tls,
+}
+
+/// The thread-local storage template of a program image, its `PT_TLS` segment.
+/// Every thread gets its own block initialized from the template, `tp` points at its start.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Tls {
+    /// Address of the initialized data (`.tdata`) in the loaded image.
+    pub addr: usize,
+    pub file_size: usize,
+    /// Size of the block including the zeroed data (`.tbss`).
+    pub mem_size: usize,
+    pub align: usize,
+}
+impl Tls {
+    /// Returns the address of a block placed right below `top`.
+    /// It keeps the stack aligned if `top` is the top of a stack.
+    pub fn block_below(&self, top: usize) -> Option<usize> {
+        let align = self.align.max(STACK_ALIGN);
+        top.checked_sub(self.mem_size)
+            .map(|addr| addr & !(align - 1))
+    }
+    /// Initializes the block at `addr` from the template.
+    ///
+    /// # Safety
+    ///
+    /// The block has to be in the memory of the user prog the image was loaded to.
+    pub unsafe fn init_block(&self, addr: usize) {
+        core::ptr::copy_nonoverlapping(self.addr as *const u8, addr as *mut u8, self.file_size);
+        core::ptr::write_bytes(
+            (addr + self.file_size) as *mut u8,
+            0,
+            self.mem_size - self.file_size,
+        );
+    }
+}
+
+/// Copies the loadable segments of the image to their addresses and zeroes the rest of each segment.
+/// The segments have to leave room for a TLS block at the end of the memory, it belongs to the
+/// first thread.
+pub fn load(image: &[u8], memory: Range<usize>) -> Result<Image, Error> {
+    if image.get(..MAGIC.len()) != Some(MAGIC)
+        || image.get(4) != Some(&CLASS_64)
+        || image.get(5) != Some(&DATA_LITTLE_ENDIAN)
+        || read_u16(image, 18)? != MACHINE_RISCV
+    {
+        return Err(Error::InvalidImage);
+    }
+    let entry = read_u64(image, 24)?;
+    let ph_offset = read_u64(image, 32)?;
+    let ph_size = read_u16(image, 54)? as usize;
+    let ph_count = read_u16(image, 56)? as usize;
+    if !memory.contains(&entry) {
+        return Err(Error::OutOfRange);
+    }
+    let mut tls = Tls::default();
+    let mut end = memory.start;
+    for idx in 0..ph_count {
+        let header = ph_offset + idx * ph_size;
+        let kind = read_u32(image, header)?;
+        let offset = read_u64(image, header + 8)?;
+        let addr = read_u64(image, header + 16)?;
+        let file_size = read_u64(image, header + 32)?;
+        let mem_size = read_u64(image, header + 40)?;
+        if kind == PT_TLS {
+            let align = read_u64(image, header + 48)?.max(1);
+            if file_size > mem_size || !align.is_power_of_two() {
+                return Err(Error::InvalidImage);
+            }
+            tls = Tls {
+                addr,
+                file_size,
+                mem_size,
+                align,
+            };
+            continue;
+        }
+        if kind != PT_LOAD {
+            continue;
+        }
+        let data = image
+            .get(offset..offset.saturating_add(file_size))
+            .ok_or(Error::InvalidImage)?;
+        if file_size > mem_size || addr < memory.start || addr.saturating_add(mem_size) > memory.end
+        {
+            return Err(Error::OutOfRange);
+        }
+        unsafe {
+            core::ptr::copy_nonoverlapping(data.as_ptr(), addr as *mut u8, file_size);
+            core::ptr::write_bytes((addr + file_size) as *mut u8, 0, mem_size - file_size);
+        }
+        end = end.max(addr + mem_size);
+    }
+    // The template is part of a loadable segment.
+    if tls.mem_size != 0
+        && (tls.addr < memory.start || tls.addr.saturating_add(tls.file_size) > end)
+    {
+        return Err(Error::OutOfRange);
+    }
+    if tls.block_below(memory.end).is_none_or(|block| block < end) {
+        return Err(Error::OutOfRange);
+    }
+    Ok(Image { entry, tls })
+}
+
+fn read<const N: usize>(image: &[u8], offset: usize) -> Result<[u8; N], Error> {
+    image
+        .get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(Error::InvalidImage)
+}
+fn read_u16(image: &[u8], offset: usize) -> Result<u16, Error> {
+    read(image, offset).map(u16::from_le_bytes)
+}
+fn read_u32(image: &[u8], offset: usize) -> Result<u32, Error> {
+    read(image, offset).map(u32::from_le_bytes)
+}
+fn read_u64(image: &[u8], offset: usize) -> Result<usize, Error> {
+    read(image, offset).map(|bytes| u64::from_le_bytes(bytes) as usize)
+}
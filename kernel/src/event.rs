@@ -0,0 +1,93 @@
+//! event -- Named event flags.
+//!
+//! User progs wait for any flag of a mask to be set and are blocked with [Reason::Event].
+//! Flags stay set until they are cleared.
+
+use riscv_utils::*;
+
+use crate::{
+    hardware::{clint, sync::Protected},
+    name::{self, Name, Named},
+    scheduler::{self, Reason},
+    sys_call::sys_yield,
+};
+
+const EVENTS: usize = 8;
+
+static EVENT_LIST: Protected<[Option<Event>; EVENTS]> = Protected::new([None; EVENTS]);
+
+#[derive(Clone, Copy)]
+struct Event {
+    name: Name,
+    flags: usize,
+}
+impl Named for Event {
+    fn name(&self) -> Name {
+        self.name
+    }
+}
+
+/// Returns the handle of the event with the name.
+/// The event is created without any flags set if it does not exist.
+pub fn create(name: &[u8]) -> Result<usize, SysCallError> {
+    let name = Name::new(name)?;
+    name::find_or_create(&mut *EVENT_LIST.lock(), name, |_| Event { name, flags: 0 })
+}
+
+/// Returns the set flags of the mask or blocks the current user prog until one of them is set.
+/// A `timeout` of 0 waits forever, otherwise it is measured in timer ticks.
+///
+/// Returns [None] if the user prog is blocked. The return value is written on wake.
+pub fn wait(handle: usize, mask: usize, timeout: usize) -> Option<usize> {
+    let cur = scheduler::cur();
+    cur.increment_mepc();
+    let event_list = EVENT_LIST.lock();
+    let Some(Some(event)) = event_list.get(handle) else {
+        return Some(to_ret(Err(SysCallError::InvalidArgument)));
+    };
+    if mask == 0 {
+        return Some(to_ret(Err(SysCallError::InvalidArgument)));
+    }
+    if event.flags & mask != 0 {
+        return Some(event.flags & mask);
+    }
+    let reason = Reason::Event { handle, mask };
+    // Blocked while holding the lock, so a set on another hart cannot miss the user prog.
+    if timeout == 0 {
+        cur.set_blocked(reason);
+    } else {
+        let timeout = clint::mtime().saturating_add(timeout as u64);
+        cur.set_blocked_until(reason, timeout);
+    }
+    event_list.unlock();
+    sys_yield();
+    None
+}
+
+/// Sets the flags and wakes all user progs waiting for one of them.
+pub fn set(handle: usize, flags: usize) -> Result<usize, SysCallError> {
+    let mut event_list = EVENT_LIST.lock();
+    let Some(Some(event)) = event_list.get_mut(handle) else {
+        return Err(SysCallError::InvalidArgument);
+    };
+    event.flags |= flags;
+    let flags = event.flags;
+    while let Some((prog, reason)) = scheduler::find_blocked_by(
+        |reason| matches!(reason, Reason::Event { handle: h, mask } if h == handle && mask & flags != 0),
+    ) {
+        if let Reason::Event { mask, .. } = reason {
+            prog.set_rdy_with_ret(flags & mask);
+        }
+    }
+    Ok(flags)
+}
+
+/// Clears the flags. Returns the remaining flags.
+pub fn clear(handle: usize, flags: usize) -> Result<usize, SysCallError> {
+    let mut event_list = EVENT_LIST.lock();
+    let Some(Some(event)) = event_list.get_mut(handle) else {
+        return Err(SysCallError::InvalidArgument);
+    };
+    event.flags &= !flags;
+    Ok(event.flags)
+}
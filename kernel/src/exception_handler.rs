@@ -1,8 +1,9 @@
 //! The exception handler.
-//! Called from `exception.S` whenever an exception or interrupt occurs.
+//! Called from the entry points of the vectored trap table in `exception.S`.
 
 use crate::{
-    hardware::{binary_struct::BinaryStruct, clint, plic, stack::Stack, uart},
+    asm, deferred,
+    hardware::{binary_struct::BinaryStruct, clint, ipi, plic, stack::Stack, sync},
     scheduler,
 };
 
@@ -11,59 +12,156 @@ use riscv_utils::*;
 
 #[no_mangle]
 unsafe extern "C" fn exception_handler(mepc: usize, mcause: usize, sp: usize) -> usize {
-    scheduler::save_cur_prog(mepc, sp);
+    trap(mepc, sp, || {
+        let mut mcause = BinaryStruct::from(mcause);
+        let interrupt = mcause.is_set(63);
+        if interrupt {
+            mcause.at(63, false);
+            handle_interrupt(mcause.into_inner());
+        } else {
+            handle_exception(mcause.into_inner(), mepc, sp);
+        }
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn software_interrupt_handler(mepc: usize, _mcause: usize, sp: usize) -> usize {
+    trap(mepc, sp, || handle_interrupt(MCAUSE_INTERRUPT_SOFTWARE))
+}
+
+#[no_mangle]
+unsafe extern "C" fn timer_interrupt_handler(mepc: usize, _mcause: usize, sp: usize) -> usize {
+    trap(mepc, sp, || handle_interrupt(MCAUSE_INTERRUPT_TIMER))
+}
+
+#[no_mangle]
+unsafe extern "C" fn external_interrupt_handler(mepc: usize, _mcause: usize, sp: usize) -> usize {
+    trap(mepc, sp, || handle_interrupt(MCAUSE_INTERRUPT_EXTERN))
+}
+
+/// Called for traps taken while the kernel handles an interrupt with interrupts enabled.
+/// Higher priority external interrupts are handled right away. The timer interrupt wakes the
+/// threads whose timeout expired right away, so idle harts can run them. Switching the user prog
+/// of the hart and software interrupts have to wait, both are masked in `mie` and handled after
+/// the preempted handler or deferred work returned.
+#[no_mangle]
+unsafe extern "C" fn nested_trap_handler(mcause: usize, mepc: usize) {
     let mut mcause = BinaryStruct::from(mcause);
-    let interrupt = mcause.is_set(63);
-    if interrupt {
-        mcause.at(63, false);
-        handle_interrupt(mcause.into_inner());
-    } else {
-        handle_exception(mcause.into_inner(), mepc, sp);
+    if !mcause.is_set(63) {
+        panic!(
+            "Exception in the kernel with code: {}, mepc: 0x{:x}",
+            mcause.into_inner(),
+            mepc
+        );
     }
+    mcause.at(63, false);
+    match mcause.into_inner() {
+        MCAUSE_INTERRUPT_EXTERN => plic::handle_interrupt(),
+        MCAUSE_INTERRUPT_TIMER => {
+            // No lock is held, as locks disable interrupts.
+            scheduler::wake_expired(clint::mtime());
+            core::arch::asm!("csrc mie, {}", in(reg) 1usize << MCAUSE_INTERRUPT_TIMER);
+        }
+        MCAUSE_INTERRUPT_SOFTWARE => {
+            core::arch::asm!("csrc mie, {}", in(reg) 1usize << MCAUSE_INTERRUPT_SOFTWARE);
+        }
+        code => panic!("Unsupported nested interrupt with code: {}", code),
+    }
+}
+
+/// Saves the user prog, handles the trap and returns the stack pointer of the next user prog.
+unsafe fn trap(mepc: usize, sp: usize, handle: impl FnOnce()) -> usize {
+    scheduler::save_cur_prog(mepc, sp);
+    handle();
+    idle();
     scheduler::restore_cur_prog()
 }
 
+/// Runs the user progs on the calling hart after its setup.
+/// The hart enters the trap handling like `trap_entry` in `exception.S`, so interrupts taken
+/// while it runs deferred work before its first user prog are nested.
+pub unsafe fn run_hart() -> ! {
+    core::arch::asm!(
+        "csrr {0}, mscratch",
+        "li {1}, 1",
+        "sd {1}, 0({0})",
+        out(reg) _,
+        out(reg) _,
+    );
+    idle();
+    asm::trap_return(scheduler::restore_cur_prog())
+}
+
+/// Runs the deferred work and waits while no user prog can run on the hart.
+/// Interrupts stay disabled, pending ones are handled by polling `mip`.
+unsafe fn idle() {
+    loop {
+        run_deferred();
+        if !scheduler::is_running() {
+            scheduler::schedule();
+        }
+        if scheduler::is_running() {
+            return;
+        }
+        clint::set_time_cmp_within_slice();
+        core::arch::asm!("wfi");
+        let mip: usize;
+        read_machine_reg!("mip" => mip);
+        for code in [
+            MCAUSE_INTERRUPT_SOFTWARE,
+            MCAUSE_INTERRUPT_TIMER,
+            MCAUSE_INTERRUPT_EXTERN,
+        ] {
+            if mip & (1 << code) != 0 {
+                handle_interrupt(code);
+            }
+        }
+    }
+}
+
 unsafe fn handle_interrupt(mcause: usize) {
     match mcause {
+        MCAUSE_INTERRUPT_SOFTWARE => {
+            if ipi::handle_interrupt() {
+                scheduler::schedule();
+            }
+        }
         MCAUSE_INTERRUPT_TIMER => {
-            let next = scheduler::next();
-            scheduler::switch(
-                next.expect("No available next user prog. Idle task not implemented"),
-            );
+            scheduler::wake_expired(clint::mtime());
+            scheduler::schedule();
             clint::set_time_cmp();
         }
-        MCAUSE_INTERRUPT_EXTERN => {
-            let irq = plic::read_claim();
-            match irq {
-                plic::Irq::Uart => {
-                    if uart::get_interrupt_cause() == uart::Interrupt::ReceivedDataRdy {
-                        if let Some(uart_prog) = uart::read_char_to_buffer() {
-                            if uart_prog.is_blocked(scheduler::Reason::Uart) {
-                                let char = uart::get_char()
-                                    .expect("Char should be present as it was just read");
-                                let mut stack = Stack::new(uart_prog.sp());
-                                stack.set_ret(char as usize);
-                                stack.write();
-                                uart_prog.set_rdy();
-                                scheduler::switch(uart_prog);
-                            }
-                        }
-                    } else {
-                        panic!(
-                            "Unsupported UART interrupt with code: {:?}",
-                            uart::get_interrupt_cause()
-                        );
-                    }
-                }
-            }
-            plic::write_complete(irq);
-        }
+        MCAUSE_INTERRUPT_EXTERN => plic::handle_interrupt(),
         _ => {
             panic!("Unsupported interrupt with code: {}", mcause);
         }
     }
 }
 
+/// Runs the work deferred by interrupt handlers with interrupts enabled.
+/// Afterwards the timer and software interrupts masked while it ran are handled.
+unsafe fn run_deferred() {
+    loop {
+        sync::with_interrupts(deferred::run);
+        handle_masked();
+        if deferred::is_empty() {
+            return;
+        }
+    }
+}
+
+/// Unmasks the timer and software interrupts masked by [nested_trap_handler] and handles them.
+unsafe fn handle_masked() {
+    for code in [MCAUSE_INTERRUPT_SOFTWARE, MCAUSE_INTERRUPT_TIMER] {
+        let mie: usize;
+        read_machine_reg!("mie" => mie);
+        if mie & (1 << code) == 0 {
+            core::arch::asm!("csrs mie, {}", in(reg) 1usize << code);
+            handle_interrupt(code);
+        }
+    }
+}
+
 unsafe fn handle_exception(mcause: usize, mepc: usize, sp: usize) {
     match mcause {
         MCAUSE_EXCEPTION_IAF => {
@@ -101,11 +199,13 @@ unsafe fn handle_exception(mcause: usize, mepc: usize, sp: usize) {
             let number = stack.a7();
             let param_0 = stack.a0();
             let param_1 = stack.a1();
-            if let Some(ret) = sys_call::sys_call(number, param_0, param_1) {
+            let param_2 = stack.a2();
+            if let Some(ret) = sys_call::sys_call(number, param_0, param_1, param_2) {
                 stack.set_ret(ret);
                 stack.write();
             }
         }
+        MCAUSE_EXCEPTION_ECALL_M => scheduler::kernel_thread_yield(),
         _ => {
             // Unsupported exception
             panic!("Unsupported exception with code: {}", mcause);
@@ -0,0 +1,775 @@
+//! fat32 -- FAT32 file system on the block device.
+//!
+//! The file system has to start at sector 0, as created by `mkfs.vfat` on an image without a
+//! partition table. Only short 8.3 names are supported, long name entries are skipped.
+//! Lower case names are stored with the case flags used by Windows NT and Linux.
+//! The free cluster count of the FSInfo sector is not maintained.
+//!
+//! [More Info](https://academy.cba.mit.edu/classes/networking_communications/SD/FAT.pdf)
+
+use riscv_utils::{FileKind, Stat, SysCallError};
+
+use crate::{
+    block::{self, BlockDevice, SECTOR_SIZE},
+    hardware::sync::Protected,
+    scheduler::Prog,
+};
+
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const DIR_ENTRY_SIZE: usize = 32;
+const DIR_ENTRIES: usize = SECTOR_SIZE / DIR_ENTRY_SIZE;
+const FAT_ENTRIES: usize = SECTOR_SIZE / 4;
+/// The upper 4 bits of a FAT entry are reserved.
+const FAT_MASK: u32 = 0x0fff_ffff;
+const FREE: u32 = 0;
+/// Marks the last cluster of a chain. Values from [END_OF_CHAIN_MIN] on mark the end as well.
+const END_OF_CHAIN: u32 = 0x0fff_ffff;
+const END_OF_CHAIN_MIN: u32 = 0x0fff_fff8;
+const FIRST_CLUSTER: u32 = 2;
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0f;
+/// First name byte of a deleted entry.
+const DELETED: u8 = 0xe5;
+/// First name byte of the first free entry. No entries follow.
+const END_OF_DIR: u8 = 0;
+const CASE_LOWER_BASE: u8 = 0x08;
+const CASE_LOWER_EXT: u8 = 0x10;
+const DOT: [u8; 11] = *b".          ";
+const DOT_DOT: [u8; 11] = *b"..         ";
+
+/// Maximum length of a displayed 8.3 name.
+pub const NAME_LEN: usize = 12;
+const HOLDS: usize = 16;
+
+static FAT32: Protected<Option<Fat32>> = Protected::new(None);
+/// The nodes held open by user progs. Only changed while holding [FAT32].
+static HOLD_LIST: Protected<[Option<Hold>; HOLDS]> = Protected::new([None; HOLDS]);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+    Block(block::Error),
+    /// No FAT32 file system was found on the block device.
+    NotMounted,
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    /// The node is held open by a user prog.
+    Busy,
+    /// The name is no valid 8.3 name.
+    InvalidName,
+    /// Writing at the offset would leave a hole in the file.
+    InvalidOffset,
+    /// No free cluster is left.
+    NoSpace,
+}
+impl From<block::Error> for Error {
+    fn from(err: block::Error) -> Self {
+        Error::Block(err)
+    }
+}
+impl From<Error> for SysCallError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Block(err) => err.into(),
+            Error::NotMounted => SysCallError::NotSupported,
+            Error::NotFound => SysCallError::NotFound,
+            Error::AlreadyExists => SysCallError::AlreadyExists,
+            Error::DirectoryNotEmpty => SysCallError::DirectoryNotEmpty,
+            Error::Busy => SysCallError::Busy,
+            Error::NoSpace => SysCallError::NoSpace,
+            Error::NotADirectory
+            | Error::IsADirectory
+            | Error::InvalidName
+            | Error::InvalidOffset => SysCallError::InvalidArgument,
+        }
+    }
+}
+
+/// A file or directory. Refers to its directory entry, so the size is never outdated.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Node {
+    /// [None] for the root directory, which has no directory entry.
+    pos: Option<EntryPos>,
+}
+impl Node {
+    const ROOT: Node = Node { pos: None };
+}
+
+/// A node opened by a user prog.
+#[derive(Clone, Copy, PartialEq)]
+struct Hold {
+    node: Node,
+    prog: Prog,
+}
+
+/// Position of a directory entry on the block device.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct EntryPos {
+    sector: u64,
+    idx: usize,
+}
+
+/// Mounts the file system of the block device. Returns false if none is found.
+pub fn init() -> bool {
+    let Some(device) = block::device() else {
+        return false;
+    };
+    match Fat32::mount(device) {
+        Ok(fat32) => {
+            crate::println!("fat32: {} clusters", fat32.clusters);
+            *FAT32.lock() = Some(fat32);
+            true
+        }
+        Err(err) => {
+            crate::println!("fat32: not mounted: {:?}", err);
+            false
+        }
+    }
+}
+
+/// Returns the node at the path relative to the root directory.
+pub fn find(path: &[u8]) -> Result<Node, Error> {
+    with(|fat32| fat32.find(path))
+}
+
+/// Holds the node for the user prog until [release]. Called for every open of the node.
+pub fn open(node: Node, prog: Prog) -> Result<(), Error> {
+    with(|fat32| {
+        // The node could have been removed since it was found.
+        if !fat32.entry(node)?.is_file_or_dir() {
+            return Err(Error::NotFound);
+        }
+        let hold = Some(Hold { node, prog });
+        let mut hold_list = HOLD_LIST.lock();
+        if hold_list.contains(&hold) {
+            return Ok(());
+        }
+        let slot = hold_list
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(Error::NoSpace)?;
+        *slot = hold;
+        Ok(())
+    })
+}
+
+/// Called when the last file descriptor of the user prog referencing the node is closed.
+pub fn release(node: Node, prog: Prog) {
+    let hold = Some(Hold { node, prog });
+    if let Some(slot) = HOLD_LIST.lock().iter_mut().find(|slot| **slot == hold) {
+        *slot = None;
+    }
+}
+
+pub fn stat(node: Node) -> Result<Stat, Error> {
+    with(|fat32| {
+        let entry = fat32.entry(node)?;
+        Ok(Stat {
+            size: entry.size as usize,
+            kind: entry.kind(),
+        })
+    })
+}
+
+/// Reads the file from the offset into the buffer. Returns the number of bytes read.
+pub fn read(node: Node, offset: usize, buf: &mut [u8]) -> Result<usize, Error> {
+    with(|fat32| fat32.read(node, offset, buf))
+}
+
+/// Writes the buffer to the file at the offset, growing the file if necessary.
+/// Returns the number of bytes written.
+pub fn write(node: Node, offset: usize, buf: &[u8]) -> Result<usize, Error> {
+    with(|fat32| fat32.write(node, offset, buf))
+}
+
+/// Creates an empty file or directory at the path.
+pub fn create(path: &[u8], kind: FileKind) -> Result<Node, Error> {
+    with(|fat32| fat32.create(path, kind))
+}
+
+/// Deletes the file or the empty directory at the path. Nodes held open cannot be deleted.
+pub fn remove(path: &[u8]) -> Result<(), Error> {
+    with(|fat32| fat32.remove(path))
+}
+
+/// Copies the name of the entry of the directory at the index into the buffer.
+/// Returns the length of the name or [None] past the last entry.
+pub fn child(node: Node, idx: usize, name: &mut [u8; NAME_LEN]) -> Result<Option<usize>, Error> {
+    with(|fat32| {
+        let dir = fat32.dir_cluster(node)?;
+        let mut count = 0;
+        fat32.find_in_dir(dir, |_, raw| {
+            let entry = DirEntry::parse(raw);
+            if !entry.is_visible() {
+                return None;
+            }
+            if count == idx {
+                return Some(entry.display_name(name));
+            }
+            count += 1;
+            None
+        })
+    })
+}
+
+fn with<R>(f: impl FnOnce(&Fat32) -> Result<R, Error>) -> Result<R, Error> {
+    let fat32 = FAT32.lock();
+    f(fat32.as_ref().ok_or(Error::NotMounted)?)
+}
+
+struct Fat32 {
+    device: &'static dyn BlockDevice,
+    sectors_per_cluster: u64,
+    fat_start: u64,
+    fat_size: u64,
+    fats: u64,
+    data_start: u64,
+    root_cluster: u32,
+    /// Number of data clusters.
+    clusters: u32,
+}
+impl Fat32 {
+    fn mount(device: &'static dyn BlockDevice) -> Result<Fat32, Error> {
+        let mut boot = [0; SECTOR_SIZE];
+        device.read(0, &mut boot)?;
+        let bytes_per_sector = u16_at(&boot, 11) as usize;
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved = u16_at(&boot, 14) as u64;
+        let fats = boot[16] as u64;
+        let fat_size_16 = u16_at(&boot, 22);
+        let total = u32_at(&boot, 32) as u64;
+        let fat_size = u32_at(&boot, 36) as u64;
+        if boot[510..] != BOOT_SIGNATURE
+            || bytes_per_sector != SECTOR_SIZE
+            || sectors_per_cluster == 0
+            || fats == 0
+            || fat_size_16 != 0
+            || fat_size == 0
+        {
+            return Err(Error::NotMounted);
+        }
+        let data_start = reserved + fats * fat_size;
+        let clusters = (total.saturating_sub(data_start) / sectors_per_cluster) as u32;
+        Ok(Fat32 {
+            device,
+            sectors_per_cluster,
+            fat_start: reserved,
+            fat_size,
+            fats,
+            data_start,
+            root_cluster: u32_at(&boot, 44),
+            clusters,
+        })
+    }
+
+    fn read_sector(&self, sector: u64) -> Result<[u8; SECTOR_SIZE], Error> {
+        let mut buf = [0; SECTOR_SIZE];
+        self.device.read(sector, &mut buf)?;
+        Ok(buf)
+    }
+    fn write_sector(&self, sector: u64, buf: &[u8; SECTOR_SIZE]) -> Result<(), Error> {
+        Ok(self.device.write(sector, buf)?)
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster as usize * SECTOR_SIZE
+    }
+    fn cluster_sector(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster - FIRST_CLUSTER) as u64 * self.sectors_per_cluster
+    }
+
+    fn fat_entry(&self, cluster: u32) -> Result<u32, Error> {
+        let idx = cluster as usize;
+        let sector = self.read_sector(self.fat_start + (idx / FAT_ENTRIES) as u64)?;
+        Ok(u32_at(&sector, idx % FAT_ENTRIES * 4) & FAT_MASK)
+    }
+    /// Sets the entry in every copy of the FAT.
+    fn set_fat_entry(&self, cluster: u32, val: u32) -> Result<(), Error> {
+        let idx = cluster as usize;
+        let offset = idx % FAT_ENTRIES * 4;
+        for fat in 0..self.fats {
+            let sector_idx = self.fat_start + fat * self.fat_size + (idx / FAT_ENTRIES) as u64;
+            let mut sector = self.read_sector(sector_idx)?;
+            let reserved = u32_at(&sector, offset) & !FAT_MASK;
+            sector[offset..offset + 4].copy_from_slice(&(reserved | val).to_le_bytes());
+            self.write_sector(sector_idx, &sector)?;
+        }
+        Ok(())
+    }
+    /// Returns the next cluster of the chain or [None] at the end of the chain.
+    fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, Error> {
+        let next = self.fat_entry(cluster)?;
+        if !(FIRST_CLUSTER..END_OF_CHAIN_MIN).contains(&next) {
+            return Ok(None);
+        }
+        Ok(Some(next))
+    }
+    /// Allocates a free cluster and appends it to the chain ending with `last`.
+    fn alloc_cluster(&self, last: Option<u32>) -> Result<u32, Error> {
+        let end = FIRST_CLUSTER as usize + self.clusters as usize;
+        for fat_sector in 0..end.div_ceil(FAT_ENTRIES) {
+            let sector = self.read_sector(self.fat_start + fat_sector as u64)?;
+            for idx in 0..FAT_ENTRIES {
+                let cluster = fat_sector * FAT_ENTRIES + idx;
+                if cluster < FIRST_CLUSTER as usize || cluster >= end {
+                    continue;
+                }
+                if u32_at(&sector, idx * 4) & FAT_MASK == FREE {
+                    let cluster = cluster as u32;
+                    self.set_fat_entry(cluster, END_OF_CHAIN)?;
+                    if let Some(last) = last {
+                        self.set_fat_entry(last, cluster)?;
+                    }
+                    return Ok(cluster);
+                }
+            }
+        }
+        Err(Error::NoSpace)
+    }
+    fn free_chain(&self, first: u32) -> Result<(), Error> {
+        let mut cluster = Some(first);
+        while let Some(cur) = cluster {
+            cluster = self.next_cluster(cur)?;
+            self.set_fat_entry(cur, FREE)?;
+        }
+        Ok(())
+    }
+    fn zero_cluster(&self, cluster: u32) -> Result<(), Error> {
+        let start = self.cluster_sector(cluster);
+        for sector in start..start + self.sectors_per_cluster {
+            self.write_sector(sector, &[0; SECTOR_SIZE])?;
+        }
+        Ok(())
+    }
+
+    fn entry(&self, node: Node) -> Result<DirEntry, Error> {
+        match node.pos {
+            None => Ok(DirEntry::new(DOT, 0, ATTR_DIRECTORY, self.root_cluster)),
+            Some(pos) => {
+                let sector = self.read_sector(pos.sector)?;
+                Ok(DirEntry::parse(&sector[pos.idx * DIR_ENTRY_SIZE..]))
+            }
+        }
+    }
+    fn update_entry(&self, pos: EntryPos, entry: &DirEntry) -> Result<(), Error> {
+        let mut sector = self.read_sector(pos.sector)?;
+        entry.store(&mut sector[pos.idx * DIR_ENTRY_SIZE..]);
+        self.write_sector(pos.sector, &sector)
+    }
+    /// Returns the first cluster of the directory.
+    fn dir_cluster(&self, node: Node) -> Result<u32, Error> {
+        let entry = self.entry(node)?;
+        if entry.kind() != FileKind::Dir {
+            return Err(Error::NotADirectory);
+        }
+        // The `..` entry of a directory in the root directory refers to cluster 0.
+        if entry.cluster == 0 {
+            return Ok(self.root_cluster);
+        }
+        Ok(entry.cluster)
+    }
+
+    /// Calls `f` with the raw directory entries until it returns a value or the end of the
+    /// directory is reached.
+    fn find_in_dir<R>(
+        &self,
+        dir: u32,
+        mut f: impl FnMut(EntryPos, &[u8]) -> Option<R>,
+    ) -> Result<Option<R>, Error> {
+        let mut cluster = Some(dir);
+        while let Some(cur) = cluster {
+            let start = self.cluster_sector(cur);
+            for sector_idx in start..start + self.sectors_per_cluster {
+                let sector = self.read_sector(sector_idx)?;
+                for idx in 0..DIR_ENTRIES {
+                    let raw = &sector[idx * DIR_ENTRY_SIZE..(idx + 1) * DIR_ENTRY_SIZE];
+                    if raw[0] == END_OF_DIR {
+                        return Ok(None);
+                    }
+                    let pos = EntryPos {
+                        sector: sector_idx,
+                        idx,
+                    };
+                    if let Some(res) = f(pos, raw) {
+                        return Ok(Some(res));
+                    }
+                }
+            }
+            cluster = self.next_cluster(cur)?;
+        }
+        Ok(None)
+    }
+    fn lookup(&self, dir: u32, name: &[u8; 11]) -> Result<Option<Node>, Error> {
+        self.find_in_dir(dir, |pos, raw| {
+            let entry = DirEntry::parse(raw);
+            if entry.is_file_or_dir() && entry.name == *name {
+                return Some(Node { pos: Some(pos) });
+            }
+            None
+        })
+    }
+    fn find(&self, path: &[u8]) -> Result<Node, Error> {
+        let mut node = Node::ROOT;
+        for component in path.split(|&byte| byte == b'/') {
+            if component.is_empty() || component == b"." {
+                continue;
+            }
+            if component == b".." && node == Node::ROOT {
+                continue;
+            }
+            let (name, _) = short_name(component)?;
+            node = self
+                .lookup(self.dir_cluster(node)?, &name)?
+                .ok_or(Error::NotFound)?;
+            if self.entry(node)?.is_root_ref() {
+                node = Node::ROOT;
+            }
+        }
+        Ok(node)
+    }
+    /// Returns the parent directory and the last component of the path.
+    fn find_parent<'a>(&self, path: &'a [u8]) -> Result<(Node, &'a [u8]), Error> {
+        let path = path.strip_suffix(b"/").unwrap_or(path);
+        match path.iter().rposition(|&byte| byte == b'/') {
+            Some(idx) => Ok((self.find(&path[..idx])?, &path[idx + 1..])),
+            None => Ok((Node::ROOT, path)),
+        }
+    }
+
+    fn read(&self, node: Node, offset: usize, buf: &mut [u8]) -> Result<usize, Error> {
+        let entry = self.entry(node)?;
+        if entry.kind() == FileKind::Dir {
+            return Err(Error::IsADirectory);
+        }
+        let size = entry.size as usize;
+        if offset >= size {
+            return Ok(0);
+        }
+        let count = buf.len().min(size - offset);
+        let mut cluster = self.seek(entry.cluster, offset)?;
+        let mut done = 0;
+        while done < count {
+            let Some(cur) = cluster else {
+                break;
+            };
+            let pos = offset + done;
+            let sector_offset = pos % SECTOR_SIZE;
+            let sector_idx = (pos % self.cluster_size() / SECTOR_SIZE) as u64;
+            let sector = self.read_sector(self.cluster_sector(cur) + sector_idx)?;
+            let len = (SECTOR_SIZE - sector_offset).min(count - done);
+            buf[done..done + len].copy_from_slice(&sector[sector_offset..sector_offset + len]);
+            done += len;
+            if (offset + done).is_multiple_of(self.cluster_size()) {
+                cluster = self.next_cluster(cur)?;
+            }
+        }
+        Ok(done)
+    }
+    fn write(&self, node: Node, offset: usize, buf: &[u8]) -> Result<usize, Error> {
+        let mut entry = self.entry(node)?;
+        let Some(pos) = node.pos.filter(|_| entry.kind() == FileKind::File) else {
+            return Err(Error::IsADirectory);
+        };
+        if offset > entry.size as usize {
+            return Err(Error::InvalidOffset);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if entry.cluster == 0 {
+            entry.cluster = self.alloc_cluster(None)?;
+        }
+        // Seek to the cluster before the offset, as a cluster boundary might need a new cluster.
+        let mut cluster = self
+            .seek(entry.cluster, offset.saturating_sub(1))?
+            .ok_or(Error::InvalidOffset)?;
+        let mut done = 0;
+        // Errors end the loop, so the entry still records the allocated clusters.
+        let mut result = Ok(());
+        while done < buf.len() {
+            let pos = offset + done;
+            if pos > 0 && pos.is_multiple_of(self.cluster_size()) {
+                let next = match self.next_cluster(cluster) {
+                    Ok(Some(next)) => Ok(next),
+                    Ok(None) => self.alloc_cluster(Some(cluster)),
+                    Err(err) => Err(err),
+                };
+                match next {
+                    Ok(next) => cluster = next,
+                    Err(err) => {
+                        result = Err(err);
+                        break;
+                    }
+                }
+            }
+            let sector_offset = pos % SECTOR_SIZE;
+            let sector_idx =
+                self.cluster_sector(cluster) + (pos % self.cluster_size() / SECTOR_SIZE) as u64;
+            let len = (SECTOR_SIZE - sector_offset).min(buf.len() - done);
+            let sector = if len == SECTOR_SIZE {
+                Ok([0; SECTOR_SIZE])
+            } else {
+                self.read_sector(sector_idx)
+            };
+            let written = sector.and_then(|mut sector| {
+                sector[sector_offset..sector_offset + len].copy_from_slice(&buf[done..done + len]);
+                self.write_sector(sector_idx, &sector)
+            });
+            if let Err(err) = written {
+                result = Err(err);
+                break;
+            }
+            done += len;
+        }
+        entry.size = entry.size.max((offset + done) as u32);
+        self.update_entry(pos, &entry)?;
+        match result {
+            Err(err) if done == 0 => Err(err),
+            _ => Ok(done),
+        }
+    }
+    /// Returns the cluster containing the offset of the chain starting with `first`.
+    fn seek(&self, first: u32, offset: usize) -> Result<Option<u32>, Error> {
+        let mut cluster = Some(first).filter(|&first| first >= FIRST_CLUSTER);
+        for _ in 0..offset / self.cluster_size() {
+            let Some(cur) = cluster else {
+                break;
+            };
+            cluster = self.next_cluster(cur)?;
+        }
+        Ok(cluster)
+    }
+
+    fn create(&self, path: &[u8], kind: FileKind) -> Result<Node, Error> {
+        let (parent, name) = self.find_parent(path)?;
+        let (name, case) = short_name(name)?;
+        if name == DOT || name == DOT_DOT {
+            return Err(Error::AlreadyExists);
+        }
+        let dir = self.dir_cluster(parent)?;
+        if self.lookup(dir, &name)?.is_some() {
+            return Err(Error::AlreadyExists);
+        }
+        let pos = self.free_entry(dir)?;
+        let entry = match kind {
+            FileKind::File => DirEntry::new(name, case, ATTR_ARCHIVE, 0),
+            FileKind::Dir => {
+                let cluster = self.alloc_cluster(None)?;
+                self.zero_cluster(cluster)?;
+                let mut sector = [0; SECTOR_SIZE];
+                DirEntry::new(DOT, 0, ATTR_DIRECTORY, cluster).store(&mut sector);
+                let parent_cluster = match parent.pos {
+                    None => 0,
+                    Some(_) => dir,
+                };
+                DirEntry::new(DOT_DOT, 0, ATTR_DIRECTORY, parent_cluster)
+                    .store(&mut sector[DIR_ENTRY_SIZE..]);
+                self.write_sector(self.cluster_sector(cluster), &sector)?;
+                DirEntry::new(name, case, ATTR_DIRECTORY, cluster)
+            }
+        };
+        let mut sector = self.read_sector(pos.sector)?;
+        let raw = &mut sector[pos.idx * DIR_ENTRY_SIZE..(pos.idx + 1) * DIR_ENTRY_SIZE];
+        raw.fill(0);
+        entry.store(raw);
+        self.write_sector(pos.sector, &sector)?;
+        Ok(Node { pos: Some(pos) })
+    }
+    /// Returns a free entry of the directory. The directory grows by a cluster if it is full.
+    fn free_entry(&self, dir: u32) -> Result<EntryPos, Error> {
+        let mut last = dir;
+        let mut cluster = Some(dir);
+        while let Some(cur) = cluster {
+            let start = self.cluster_sector(cur);
+            for sector_idx in start..start + self.sectors_per_cluster {
+                let sector = self.read_sector(sector_idx)?;
+                for idx in 0..DIR_ENTRIES {
+                    if matches!(sector[idx * DIR_ENTRY_SIZE], END_OF_DIR | DELETED) {
+                        return Ok(EntryPos {
+                            sector: sector_idx,
+                            idx,
+                        });
+                    }
+                }
+            }
+            last = cur;
+            cluster = self.next_cluster(cur)?;
+        }
+        let cluster = self.alloc_cluster(Some(last))?;
+        self.zero_cluster(cluster)?;
+        Ok(EntryPos {
+            sector: self.cluster_sector(cluster),
+            idx: 0,
+        })
+    }
+    fn remove(&self, path: &[u8]) -> Result<(), Error> {
+        let (parent, name) = self.find_parent(path)?;
+        let (name, _) = short_name(name)?;
+        if name == DOT || name == DOT_DOT {
+            return Err(Error::InvalidName);
+        }
+        let node = self
+            .lookup(self.dir_cluster(parent)?, &name)?
+            .ok_or(Error::NotFound)?;
+        let Some(pos) = node.pos else {
+            return Err(Error::InvalidName);
+        };
+        // Its clusters would still be used through the file descriptors.
+        if HOLD_LIST
+            .lock()
+            .iter()
+            .flatten()
+            .any(|hold| hold.node == node)
+        {
+            return Err(Error::Busy);
+        }
+        let mut entry = self.entry(node)?;
+        if entry.kind() == FileKind::Dir {
+            let not_empty = self.find_in_dir(entry.cluster, |_, raw| {
+                DirEntry::parse(raw).is_visible().then_some(())
+            })?;
+            if not_empty.is_some() {
+                return Err(Error::DirectoryNotEmpty);
+            }
+        }
+        if entry.cluster >= FIRST_CLUSTER {
+            self.free_chain(entry.cluster)?;
+        }
+        entry.name[0] = DELETED;
+        self.update_entry(pos, &entry)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct DirEntry {
+    /// Space padded base name and extension.
+    name: [u8; 11],
+    attr: u8,
+    case: u8,
+    cluster: u32,
+    size: u32,
+}
+impl DirEntry {
+    fn new(name: [u8; 11], case: u8, attr: u8, cluster: u32) -> Self {
+        DirEntry {
+            name,
+            attr,
+            case,
+            cluster,
+            size: 0,
+        }
+    }
+    fn parse(raw: &[u8]) -> Self {
+        let mut name = [0; 11];
+        name.copy_from_slice(&raw[..11]);
+        DirEntry {
+            name,
+            attr: raw[11],
+            case: raw[12],
+            cluster: ((u16_at(raw, 20) as u32) << 16) | u16_at(raw, 26) as u32,
+            size: u32_at(raw, 28),
+        }
+    }
+    /// Stores the fields into the raw entry. Other fields, e.g. timestamps, are kept.
+    fn store(&self, raw: &mut [u8]) {
+        raw[..11].copy_from_slice(&self.name);
+        raw[11] = self.attr;
+        raw[12] = self.case;
+        raw[20..22].copy_from_slice(&((self.cluster >> 16) as u16).to_le_bytes());
+        raw[26..28].copy_from_slice(&(self.cluster as u16).to_le_bytes());
+        raw[28..32].copy_from_slice(&self.size.to_le_bytes());
+    }
+    fn kind(&self) -> FileKind {
+        match self.attr & ATTR_DIRECTORY {
+            0 => FileKind::File,
+            _ => FileKind::Dir,
+        }
+    }
+    fn is_file_or_dir(&self) -> bool {
+        self.name[0] != DELETED
+            && self.attr & ATTR_LONG_NAME != ATTR_LONG_NAME
+            && self.attr & ATTR_VOLUME_ID == 0
+    }
+    /// Returns true for entries listed in a directory, i.e. without `.` and `..`.
+    fn is_visible(&self) -> bool {
+        self.is_file_or_dir() && self.name != DOT && self.name != DOT_DOT
+    }
+    /// Returns true for a `..` entry referring to the root directory.
+    fn is_root_ref(&self) -> bool {
+        self.name == DOT_DOT && self.cluster == 0
+    }
+    fn display_name(&self, buf: &mut [u8; NAME_LEN]) -> usize {
+        let mut len = 0;
+        let mut push = |bytes: &[u8], lower: bool| {
+            for &byte in bytes.iter().filter(|&&byte| byte != b' ') {
+                buf[len] = if lower {
+                    byte.to_ascii_lowercase()
+                } else {
+                    byte
+                };
+                len += 1;
+            }
+        };
+        push(&self.name[..8], self.case & CASE_LOWER_BASE != 0);
+        if self.name[8] != b' ' {
+            push(b".", false);
+            push(&self.name[8..], self.case & CASE_LOWER_EXT != 0);
+        }
+        len
+    }
+}
+
+/// Converts the name into a space padded 8.3 name and its case flags.
+fn short_name(name: &[u8]) -> Result<([u8; 11], u8), Error> {
+    let mut short = [b' '; 11];
+    if name == b"." || name == b".." {
+        short[..name.len()].copy_from_slice(name);
+        return Ok((short, 0));
+    }
+    let (base, ext) = match name.iter().rposition(|&byte| byte == b'.') {
+        Some(idx) => (&name[..idx], &name[idx + 1..]),
+        None => (name, &name[name.len()..]),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return Err(Error::InvalidName);
+    }
+    let mut case = 0;
+    let (short_base, short_ext) = short.split_at_mut(8);
+    for (part, dest, flag) in [
+        (base, short_base, CASE_LOWER_BASE),
+        (ext, short_ext, CASE_LOWER_EXT),
+    ] {
+        if !part.iter().all(|&byte| is_short_name_char(byte)) {
+            return Err(Error::InvalidName);
+        }
+        // Mixed case parts are stored in upper case.
+        if part.iter().any(u8::is_ascii_lowercase) && !part.iter().any(u8::is_ascii_uppercase) {
+            case |= flag;
+        }
+        for (dest, byte) in dest.iter_mut().zip(part) {
+            *dest = byte.to_ascii_uppercase();
+        }
+    }
+    Ok((short, case))
+}
+
+fn is_short_name_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'()-@^_`{}~".contains(&byte)
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
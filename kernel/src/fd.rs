@@ -0,0 +1,178 @@
+//! fd -- File Descriptor table of a user prog.
+//!
+//! Every I/O system call resolves its file descriptor through the table of the current user prog.
+//! A [Descriptor] describes the resource behind a file descriptor.
+
+use riscv_utils::{FileKind, SysCallError, STDERR, STDIN, STDOUT};
+
+use crate::{
+    dev,
+    hardware::uart,
+    scheduler::{Prog, Reason},
+    vfs::{self, Dentry, Inode},
+};
+
+pub const FD_TABLE_SIZE: usize = 8;
+
+/// The result of an I/O operation on a [Descriptor].
+pub enum Io {
+    /// The operation finished with the number of bytes transferred.
+    Done(usize),
+    /// The operation has to wait and must be repeated once the user prog is rdy again.
+    Blocked(Reason),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Descriptor {
+    /// The UART console. Reading requires the user prog to hold the UART by opening `/dev/uart0`.
+    Console,
+    /// A file or device of the vfs. Each file descriptor has its own offset.
+    File { inode: Inode, offset: usize },
+    /// A directory of the vfs. `idx` is the next entry returned by [Descriptor::read_dir].
+    Dir { dentry: Dentry, idx: usize },
+}
+impl Descriptor {
+    /// Opens the file, directory or device at the path for the user prog.
+    /// Nothing is held by the user prog if it fails.
+    pub fn open(prog: Prog, path: &[u8]) -> Result<Descriptor, SysCallError> {
+        let dentry = vfs::lookup(path)?;
+        let inode = dentry.inode();
+        let kind = inode.stat()?.kind;
+        inode.open(prog)?;
+        Ok(match kind {
+            FileKind::File => Descriptor::File { inode, offset: 0 },
+            FileKind::Dir => Descriptor::Dir { dentry, idx: 0 },
+        })
+    }
+    pub fn read(&mut self, prog: Prog, buf: &mut [u8]) -> Result<Io, SysCallError> {
+        match self {
+            Descriptor::Console => dev::read_uart(prog, buf),
+            Descriptor::File { inode, offset } => {
+                let io = inode.read(prog, *offset, buf)?;
+                if let Io::Done(count) = io {
+                    *offset += count;
+                }
+                Ok(io)
+            }
+            Descriptor::Dir { .. } => Err(SysCallError::InvalidArgument),
+        }
+    }
+    /// Copies the name of the next directory entry into the buffer. Returns the length of the name
+    /// or 0 after the last entry.
+    pub fn read_dir(&mut self, buf: &mut [u8]) -> Result<usize, SysCallError> {
+        let Descriptor::Dir { dentry, idx } = self else {
+            return Err(SysCallError::InvalidArgument);
+        };
+        match dentry.child(*idx, buf)? {
+            Some(len) => {
+                *idx += 1;
+                Ok(len)
+            }
+            None => Ok(0),
+        }
+    }
+    pub fn write(&mut self, buf: &[u8]) -> Result<Io, SysCallError> {
+        match self {
+            Descriptor::Console => {
+                for byte in buf {
+                    uart::print_char(*byte as char);
+                }
+                Ok(Io::Done(buf.len()))
+            }
+            Descriptor::File { inode, offset } => {
+                let io = inode.write(*offset, buf)?;
+                if let Io::Done(count) = io {
+                    *offset += count;
+                }
+                Ok(io)
+            }
+            Descriptor::Dir { .. } => Err(SysCallError::InvalidArgument),
+        }
+    }
+    /// Returns the inode behind the descriptor. [None] for the console.
+    fn inode(&self) -> Option<Inode> {
+        match self {
+            Descriptor::Console => None,
+            Descriptor::File { inode, .. } => Some(*inode),
+            Descriptor::Dir { dentry, .. } => Some(dentry.inode()),
+        }
+    }
+    /// Releases the resource when the last file descriptor referencing it is closed.
+    fn release(&self, prog: Prog) {
+        if let Some(inode) = self.inode() {
+            inode.release(prog);
+        }
+    }
+}
+
+#[derive(PartialEq)]
+pub struct FdTable([Option<Descriptor>; FD_TABLE_SIZE]);
+impl FdTable {
+    /// Creates a table with stdin, stdout and stderr opened to the console.
+    pub const fn new() -> Self {
+        let mut fds = [None; FD_TABLE_SIZE];
+        fds[STDIN] = Some(Descriptor::Console);
+        fds[STDOUT] = Some(Descriptor::Console);
+        fds[STDERR] = Some(Descriptor::Console);
+        FdTable(fds)
+    }
+    pub fn get(&self, fd: usize) -> Result<Descriptor, SysCallError> {
+        self.0.get(fd).copied().flatten().ok_or(SysCallError::BadFd)
+    }
+    /// Adds the opened descriptor at the lowest free file descriptor and returns it.
+    /// If no file descriptor is free, the resource is released unless another file descriptor
+    /// of the user prog owning the table references it.
+    pub fn open(&mut self, descriptor: Descriptor, prog: Prog) -> Result<usize, SysCallError> {
+        let Some(fd) = self.0.iter().position(Option::is_none) else {
+            let inode = descriptor.inode();
+            if !self.0.iter().flatten().any(|open| open.inode() == inode) {
+                descriptor.release(prog);
+            }
+            return Err(SysCallError::NoSpace);
+        };
+        self.0[fd] = Some(descriptor);
+        Ok(fd)
+    }
+    /// Replaces the descriptor of an open file descriptor, e.g. to update its offset.
+    pub fn set(&mut self, fd: usize, descriptor: Descriptor) -> Result<(), SysCallError> {
+        self.get(fd)?;
+        self.0[fd] = Some(descriptor);
+        Ok(())
+    }
+    /// Closes the file descriptor of the user prog owning the table.
+    pub fn close(&mut self, fd: usize, prog: Prog) -> Result<(), SysCallError> {
+        let descriptor = self.get(fd)?;
+        self.0[fd] = None;
+        let inode = descriptor.inode();
+        if inode.is_some() && !self.0.iter().flatten().any(|open| open.inode() == inode) {
+            descriptor.release(prog);
+        }
+        Ok(())
+    }
+    /// Makes `new_fd` a copy of `old_fd`, closing `new_fd` first if necessary.
+    pub fn dup2(
+        &mut self,
+        old_fd: usize,
+        new_fd: usize,
+        prog: Prog,
+    ) -> Result<usize, SysCallError> {
+        let descriptor = self.get(old_fd)?;
+        if new_fd >= FD_TABLE_SIZE {
+            return Err(SysCallError::BadFd);
+        }
+        if old_fd == new_fd {
+            return Ok(new_fd);
+        }
+        if self.0[new_fd].is_some() {
+            self.close(new_fd, prog)?;
+        }
+        self.0[new_fd] = Some(descriptor);
+        Ok(new_fd)
+    }
+    /// Closes all file descriptors, e.g. when the user prog exits.
+    pub fn close_all(&mut self, prog: Prog) {
+        for fd in 0..FD_TABLE_SIZE {
+            self.close(fd, prog).ok();
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! futex -- Fast user-space mutex.
+//!
+//! User progs wait on a 32-bit value at an address and are woken by other user progs.
+//! The waiting user progs are blocked with [Reason::Futex] keyed by the address.
+//! Waiting and waking are serialized, so a wake cannot slip in between checking the value and
+//! blocking.
+
+use riscv_utils::*;
+
+use crate::{
+    hardware::{clint, memory_mapping::MemoryMapping, sync::Protected},
+    scheduler::{self, Reason},
+    sys_call::{self, sys_yield},
+};
+
+/// Held while checking the value and blocking and while waking.
+static FUTEX_LOCK: Protected<()> = Protected::new(());
+
+/// Blocks the current user prog if the value at the address equals `expected`.
+/// A `timeout` of 0 waits forever, otherwise it is measured in timer ticks.
+///
+/// Returns [None] if the user prog is blocked. The return value is written on wake.
+pub fn wait(addr: usize, expected: usize, timeout: usize) -> Option<usize> {
+    let cur = scheduler::cur();
+    cur.increment_mepc();
+    if !addr.is_multiple_of(4) {
+        return Some(to_ret(Err(SysCallError::InvalidArgument)));
+    }
+    if let Err(err) = sys_call::check_user_memory(addr, 4) {
+        return Some(to_ret(Err(err)));
+    }
+    let lock = FUTEX_LOCK.lock();
+    let val: u32 = unsafe { MemoryMapping::new(addr).read() };
+    if val != expected as u32 {
+        return Some(to_ret(Err(SysCallError::Again)));
+    }
+    if timeout == 0 {
+        cur.set_blocked(Reason::Futex(addr));
+    } else {
+        let timeout = clint::mtime().saturating_add(timeout as u64);
+        cur.set_blocked_until(Reason::Futex(addr), timeout);
+    }
+    lock.unlock();
+    sys_yield();
+    None
+}
+
+/// Wakes up to `count` user progs waiting on the address.
+/// Returns the number of woken user progs.
+pub fn wake(addr: usize, count: usize) -> usize {
+    scheduler::cur().increment_mepc();
+    let _lock = FUTEX_LOCK.lock();
+    let mut woken = 0;
+    while woken < count {
+        match scheduler::find_blocked(Reason::Futex(addr)) {
+            Some(prog) => prog.set_rdy_with_ret(0),
+            None => break,
+        }
+        woken += 1;
+    }
+    woken
+}
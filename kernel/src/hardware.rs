@@ -1,9 +1,18 @@
 pub mod binary_struct;
 pub mod clint;
+pub mod fdt;
+pub mod hart;
+pub mod ipi;
 pub mod memory_mapping;
+pub mod pci;
+pub mod platform;
 pub mod plic;
 pub mod pmp;
-pub mod ring_buffer;
+pub mod rtc;
 pub mod stack;
 pub mod sync;
+pub mod test_finisher;
 pub mod uart;
+pub mod virtio;
+pub mod virtio_blk;
+pub mod virtio_rng;
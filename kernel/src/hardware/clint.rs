@@ -1,26 +1,50 @@
 //!  clint -- Core Local Interrupt
+//!
+//! Every hart has its own `mtimecmp` register, the functions use the one of the calling hart.
 
-use super::memory_mapping::MemoryMapping;
+use super::{hart, memory_mapping::MemoryMapping, platform};
 
 const TIMER_DURATION: u64 = 10000000;
 
-///     `mtimecmp_addr`: Address of the Compare Value for the Core Local Interrupt (clint), triggers timer interrupt **!! In QEMU at 0x0200 on real hardware at 0x2000**
-pub const MTIMECMP_ADDR: usize = 0x0200_4000;
+///     `mtimecmp`: Offset of the Compare Value for the Core Local Interrupt (clint) of hart 0, triggers timer interrupt.
+///     The registers of the following harts are 8 bytes apart.
+const MTIMECMP: usize = 0x4000;
 
-///     `mtime`: 64bit register of the timer incremented every clock-cycle e.g. 10.000.000 times on QEMU with 10Mhz
-pub const MTIME_ADDR: usize = 0x0200_BFF8;
+///     `mtime`: Offset of the 64bit register of the timer incremented every clock-cycle e.g. 10.000.000 times on QEMU with 10Mhz
+const MTIME: usize = 0xBFF8;
 
 pub fn set_time_cmp() {
     unsafe {
-        let mtimecmp = MemoryMapping::new(MTIMECMP_ADDR);
-        let mtime: u64 = MemoryMapping::new(MTIME_ADDR).read();
-        mtimecmp.write(mtime + TIMER_DURATION);
+        mtimecmp().write(mtime() + TIMER_DURATION);
     }
 }
 
+/// Returns the current value of the timer.
+pub fn mtime() -> u64 {
+    unsafe { MemoryMapping::new(platform::get().clint + MTIME).read() }
+}
+
+/// Moves the next timer interrupt forward to the deadline if it is earlier.
+pub fn set_time_cmp_before(deadline: u64) {
+    unsafe {
+        let mtimecmp = mtimecmp();
+        if deadline < mtimecmp.read() {
+            mtimecmp.write(deadline);
+        }
+    }
+}
+
+/// Makes sure the timer interrupt is raised within one time slice.
+pub fn set_time_cmp_within_slice() {
+    set_time_cmp_before(mtime() + TIMER_DURATION);
+}
+
 pub fn init() {
     unsafe {
-        let mtimecmp = MemoryMapping::new(MTIMECMP_ADDR);
-        mtimecmp.write(u64::MAX);
+        mtimecmp().write(u64::MAX);
     }
 }
+
+fn mtimecmp() -> MemoryMapping<u64> {
+    MemoryMapping::new(platform::get().clint + MTIMECMP + 8 * hart::id())
+}
@@ -0,0 +1,212 @@
+//! fdt -- Parser for the flattened device tree passed by the boot loader in `a1`.
+//!
+//! Only the structure and strings blocks are read, the memory reservation block is ignored.
+//! All values are big endian.
+//!
+//! [More Info](https://devicetree-specification.readthedocs.io/en/stable/flattened-format.html)
+
+const MAGIC: u32 = 0xd00d_feed;
+const HEADER_SIZE: usize = 40;
+
+// Header offsets.
+const TOTAL_SIZE: usize = 4;
+const OFF_DT_STRUCT: usize = 8;
+const OFF_DT_STRINGS: usize = 12;
+
+// Structure block tokens.
+const BEGIN_NODE: u32 = 1;
+const END_NODE: u32 = 2;
+const PROP: u32 = 3;
+const NOP: u32 = 4;
+
+/// Nesting depth up to which the cell sizes of parent nodes are tracked.
+const MAX_DEPTH: usize = 8;
+
+#[derive(Clone, Copy)]
+pub struct Fdt {
+    data: &'static [u8],
+    structs: usize,
+    strings: usize,
+}
+impl Fdt {
+    /// Returns [None] if there is no valid device tree at the address.
+    ///
+    /// # Safety
+    ///
+    /// The address has to be readable and stay unchanged.
+    pub unsafe fn from_addr(addr: usize) -> Option<Fdt> {
+        if addr == 0 || !addr.is_multiple_of(4) {
+            return None;
+        }
+        let header = core::slice::from_raw_parts(addr as *const u8, HEADER_SIZE);
+        if read_u32(header, 0)? != MAGIC {
+            return None;
+        }
+        let size = read_u32(header, TOTAL_SIZE)? as usize;
+        let data = core::slice::from_raw_parts(addr as *const u8, size);
+        Some(Fdt {
+            data,
+            structs: read_u32(data, OFF_DT_STRUCT)? as usize,
+            strings: read_u32(data, OFF_DT_STRINGS)? as usize,
+        })
+    }
+    /// Returns all nodes in depth-first order, starting with the root node.
+    pub fn nodes(&self) -> Nodes {
+        Nodes {
+            fdt: *self,
+            offset: self.structs,
+            depth: 0,
+            cells: [Cells::DEFAULT; MAX_DEPTH],
+        }
+    }
+    fn string(&self, offset: usize) -> Option<&'static [u8]> {
+        let data = self.data.get(self.strings + offset..)?;
+        let len = data.iter().position(|&byte| byte == 0)?;
+        Some(&data[..len])
+    }
+}
+
+/// Number of u32 cells of the addresses and sizes in `reg` properties of the children.
+#[derive(Clone, Copy)]
+struct Cells {
+    address: usize,
+    size: usize,
+}
+impl Cells {
+    const DEFAULT: Cells = Cells {
+        address: 2,
+        size: 1,
+    };
+}
+
+pub struct Nodes {
+    fdt: Fdt,
+    offset: usize,
+    depth: usize,
+    cells: [Cells; MAX_DEPTH],
+}
+impl Iterator for Nodes {
+    type Item = Node;
+    fn next(&mut self) -> Option<Node> {
+        let data = self.fdt.data;
+        loop {
+            let token = read_u32(data, self.offset)?;
+            self.offset += 4;
+            match token {
+                BEGIN_NODE => {
+                    // The name is followed by the properties.
+                    let len = data
+                        .get(self.offset..)?
+                        .iter()
+                        .position(|&byte| byte == 0)?;
+                    self.offset = align(self.offset + len + 1);
+                    let cells = match self.depth {
+                        0 => Cells::DEFAULT,
+                        depth => self.cells[(depth - 1).min(MAX_DEPTH - 1)],
+                    };
+                    let node = Node {
+                        fdt: self.fdt,
+                        props: self.offset,
+                        cells,
+                    };
+                    if self.depth < MAX_DEPTH {
+                        self.cells[self.depth] = Cells {
+                            address: node.property_u32(b"#address-cells").unwrap_or(2) as usize,
+                            size: node.property_u32(b"#size-cells").unwrap_or(1) as usize,
+                        };
+                    }
+                    self.depth += 1;
+                    return Some(node);
+                }
+                END_NODE => self.depth = self.depth.checked_sub(1)?,
+                PROP => {
+                    let len = read_u32(data, self.offset)? as usize;
+                    self.offset = align(self.offset + 8 + len);
+                }
+                NOP => {}
+                // The end token or a corrupt tree.
+                _ => return None,
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Node {
+    fdt: Fdt,
+    /// Offset of the first property.
+    props: usize,
+    /// Cell sizes of the parent node.
+    cells: Cells,
+}
+impl Node {
+    pub fn property(&self, name: &[u8]) -> Option<&'static [u8]> {
+        let data = self.fdt.data;
+        let mut offset = self.props;
+        loop {
+            match read_u32(data, offset)? {
+                PROP => {
+                    let len = read_u32(data, offset + 4)? as usize;
+                    let name_offset = read_u32(data, offset + 8)? as usize;
+                    let value = data.get(offset + 12..offset + 12 + len)?;
+                    if self.fdt.string(name_offset)? == name {
+                        return Some(value);
+                    }
+                    offset = align(offset + 12 + len);
+                }
+                NOP => offset += 4,
+                _ => return None,
+            }
+        }
+    }
+    fn property_u32(&self, name: &[u8]) -> Option<u32> {
+        read_u32(self.property(name)?, 0)
+    }
+    /// Returns true if the string list of the `compatible` property contains the string.
+    pub fn is_compatible(&self, compatible: &[u8]) -> bool {
+        self.property(b"compatible").is_some_and(|list| {
+            list.split(|&byte| byte == 0)
+                .any(|string| string == compatible)
+        })
+    }
+    /// Returns true if the `device_type` property is the type, e.g. `memory` or `cpu`.
+    pub fn is_device_type(&self, device_type: &[u8]) -> bool {
+        self.property(b"device_type")
+            .is_some_and(|value| value.strip_suffix(&[0]).unwrap_or(value) == device_type)
+    }
+    /// Returns the address and size of the first register block.
+    pub fn reg(&self) -> Option<(usize, usize)> {
+        let reg = self.property(b"reg")?;
+        let (address, size) = reg.split_at_checked(self.cells.address * 4)?;
+        Some((
+            read_cells(address)?,
+            read_cells(size.get(..self.cells.size * 4)?)?,
+        ))
+    }
+    /// Returns the first interrupt specifier.
+    pub fn interrupt(&self) -> Option<usize> {
+        self.property_u32(b"interrupts").map(|irq| irq as usize)
+    }
+}
+
+/// Combines the big endian cells to a number. Returns [None] for more than two cells.
+fn read_cells(cells: &[u8]) -> Option<usize> {
+    if cells.len() > 8 {
+        return None;
+    }
+    Some(
+        cells
+            .iter()
+            .fold(0, |num, &byte| (num << 8) | byte as usize),
+    )
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u32::from_be_bytes)
+}
+
+fn align(offset: usize) -> usize {
+    offset.next_multiple_of(4)
+}
@@ -0,0 +1,36 @@
+//! hart -- Hardware threads.
+//!
+//! All harts enter `boot.S` at the same time. The boot hart sets up the kernel while the others
+//! wait until they are released.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use riscv_utils::read_machine_reg;
+
+/// Number of harts with a kernel stack. Must match `boot.S`, further harts are parked.
+pub const MAX_HARTS: usize = 4;
+/// The hart setting up the kernel. Only it receives PLIC interrupts.
+pub const BOOT_HART: usize = 0;
+
+static RELEASED: AtomicBool = AtomicBool::new(false);
+
+/// Returns the id of the calling hart.
+pub fn id() -> usize {
+    let id: usize;
+    unsafe {
+        read_machine_reg!("mhartid" => id);
+    }
+    id
+}
+
+/// Waits until the boot hart has finished the global setup.
+pub fn wait_for_release() {
+    while !RELEASED.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+}
+
+/// Lets the other harts continue after the global setup.
+pub fn release() {
+    RELEASED.store(true, Ordering::Release);
+}
@@ -0,0 +1,95 @@
+//! ipi -- Inter-processor interrupts over the CLINT `msip` registers.
+//!
+//! Writing 1 to the `msip` register of a hart raises a machine software interrupt on it.
+//! The requests are kept as bits per hart, so one interrupt can carry several of them.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::hart::{self, MAX_HARTS};
+use super::memory_mapping::MemoryMapping;
+use super::platform;
+use super::sync::Protected;
+
+/// Offset of the `msip` register of hart 0. The registers of the following harts are 4 bytes apart.
+const MSIP: usize = 0x0000;
+
+// Request bits.
+const RESCHEDULE: usize = 1 << 0;
+const CALL: usize = 1 << 1;
+
+static PENDING: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+/// The function of a remote call. Cleared when it has finished.
+type RemoteCall = Option<fn()>;
+
+static CALLS: [Protected<RemoteCall>; MAX_HARTS] = [const { Protected::new(None) }; MAX_HARTS];
+/// Number of finished remote calls of each hart.
+static FINISHED: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+
+/// Makes the hart pick its next user prog, e.g. an idle hart after a user prog got rdy.
+pub fn reschedule(hart: usize) {
+    send(hart, RESCHEDULE);
+}
+
+/// Runs the function on the hart and waits until it has finished.
+/// The caller must not hold any lock. The hart could spin on it with interrupts disabled and
+/// never run the function.
+pub fn call(hart: usize, func: fn()) {
+    if hart == hart::id() {
+        func();
+        return;
+    }
+    let ticket = loop {
+        let mut call = CALLS[hart].lock();
+        if call.is_none() {
+            *call = Some(func);
+            break FINISHED[hart].load(Ordering::Acquire);
+        }
+        call.unlock();
+        // The hart could be waiting for a call to this hart.
+        handle_call();
+    };
+    send(hart, CALL);
+    while FINISHED[hart].load(Ordering::Acquire) == ticket {
+        handle_call();
+        core::hint::spin_loop();
+    }
+}
+
+/// Handles the machine software interrupt of the calling hart.
+/// Returns true if the hart has to reschedule.
+pub fn handle_interrupt() -> bool {
+    let hart = hart::id();
+    unsafe { msip(hart).write(0) };
+    let requests = PENDING[hart].swap(0, Ordering::AcqRel);
+    if requests & CALL != 0 {
+        run_call(hart);
+    }
+    requests & RESCHEDULE != 0
+}
+
+fn send(hart: usize, request: usize) {
+    PENDING[hart].fetch_or(request, Ordering::AcqRel);
+    unsafe { msip(hart).write(1) };
+}
+
+/// Runs a pending remote call of the calling hart. Other requests stay pending.
+fn handle_call() {
+    let hart = hart::id();
+    if PENDING[hart].fetch_and(!CALL, Ordering::AcqRel) & CALL != 0 {
+        run_call(hart);
+    }
+}
+
+fn run_call(hart: usize) {
+    let call = *CALLS[hart].lock();
+    if let Some(func) = call {
+        func();
+        let mut call = CALLS[hart].lock();
+        FINISHED[hart].fetch_add(1, Ordering::AcqRel);
+        *call = None;
+    }
+}
+
+fn msip(hart: usize) -> MemoryMapping<u32> {
+    MemoryMapping::new(platform::get().clint + MSIP + 4 * hart)
+}
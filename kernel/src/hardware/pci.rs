@@ -0,0 +1,310 @@
+//! pci -- PCI Express devices over the enhanced configuration access mechanism (ECAM).
+//!
+//! Bus 0 is enumerated at boot. Memory BARs get addresses of the MMIO window of the platform,
+//! I/O BARs and devices behind bridges are not supported.
+//! Drivers look up their device with [find] and register an interrupt handler with [set_handler].
+//!
+//! [More Info](https://wiki.osdev.org/PCI)
+
+use enum_matching::EnumTryFrom;
+
+use super::memory_mapping::MemoryMapping;
+use super::platform;
+use super::plic;
+use super::sync::Protected;
+
+/// Number of devices that can be enumerated.
+const MAX_DEVICES: usize = 16;
+const DEVICES_PER_BUS: u8 = 32;
+const FUNCTIONS_PER_DEVICE: u8 = 8;
+const BARS: usize = 6;
+const IRQ_PRIORITY: u32 = 2;
+/// PLIC irq of the first INTx pin as routed by QEMU's `virt` machine.
+const INTX_IRQ: usize = 32;
+
+// Configuration space offsets of the type 0 header.
+const VENDOR_ID: usize = 0x00;
+const COMMAND: usize = 0x04;
+const CLASS: usize = 0x08;
+const HEADER_TYPE: usize = 0x0c;
+const BAR0: usize = 0x10;
+const INTERRUPT: usize = 0x3c;
+
+/// Vendor id of empty functions.
+const NO_VENDOR: u16 = 0xffff;
+const HEADER_TYPE_MASK: u32 = 0x7f << 16;
+const HEADER_MULTI_FUNCTION: u32 = 0x80 << 16;
+const COMMAND_MEMORY: u32 = 1 << 1;
+const COMMAND_BUS_MASTER: u32 = 1 << 2;
+const BAR_IO: u32 = 1;
+const BAR_64_BIT: u32 = 0b10 << 1;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+const BAR_MEMORY_MASK: u32 = !0xf;
+
+static DEVICES: Protected<[Option<Device>; MAX_DEVICES]> = Protected::new([None; MAX_DEVICES]);
+
+/// Interrupt requests of the INTx pins. Shared by all devices with the same pin and slot swizzle.
+#[derive(EnumTryFrom, Clone, Copy, PartialEq, Debug)]
+pub enum Pin {
+    IntA = 1,
+    IntB = 2,
+    IntC = 3,
+    IntD = 4,
+}
+impl Pin {
+    /// The PLIC irq of the pin of a device in the slot as routed by QEMU's `virt` machine.
+    fn irq(self, device: u8) -> usize {
+        INTX_IRQ + (self as usize - 1 + device as usize) % 4
+    }
+}
+
+/// Bus, device and function number.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Address {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+impl Address {
+    fn config(&self, offset: usize) -> MemoryMapping<u32> {
+        let pcie = platform::get().pcie.expect("No PCIe host");
+        MemoryMapping::new(
+            pcie.ecam
+                + ((self.bus as usize) << 20)
+                + ((self.device as usize) << 15)
+                + ((self.function as usize) << 12)
+                + offset,
+        )
+    }
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { self.config(offset).read() }
+    }
+    fn write(&self, offset: usize, val: u32) {
+        unsafe { self.config(offset).write(val) }
+    }
+}
+
+/// A memory BAR with its assigned address.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Bar {
+    pub addr: usize,
+    pub size: usize,
+    pub prefetchable: bool,
+}
+
+#[derive(Clone, Copy)]
+pub struct Device {
+    pub address: Address,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    /// Indexed by BAR number. The upper half of a 64-bit BAR is [None].
+    pub bars: [Option<Bar>; BARS],
+    /// The PLIC interrupt request.
+    pub irq: Option<usize>,
+    /// Called on every interrupt of the irq, which can be shared with other devices.
+    handler: Option<fn()>,
+}
+
+/// Enumerates bus 0, assigns the memory BARs and enables the found devices.
+pub fn init() {
+    let Some(pcie) = platform::get().pcie else {
+        return;
+    };
+    let mut window = pcie.mmio..pcie.mmio + pcie.mmio_size;
+    let mut devices = DEVICES.lock();
+    let mut count = 0;
+    for device in 0..DEVICES_PER_BUS {
+        for function in 0..FUNCTIONS_PER_DEVICE {
+            let address = Address {
+                bus: 0,
+                device,
+                function,
+            };
+            let id = address.read(VENDOR_ID);
+            if id as u16 == NO_VENDOR {
+                if function == 0 {
+                    break;
+                }
+                continue;
+            }
+            let header_type = address.read(HEADER_TYPE);
+            if header_type & HEADER_TYPE_MASK == 0 {
+                let Some(slot) = devices.get_mut(count) else {
+                    crate::println!("pci: more than {} devices", MAX_DEVICES);
+                    return;
+                };
+                let found = probe(address, id, &mut window);
+                crate::println!(
+                    "pci {:02x}:{:02x}.{}: {:04x}:{:04x} class {:02x}.{:02x}.{:02x} irq {:?}",
+                    address.bus,
+                    address.device,
+                    address.function,
+                    found.vendor_id,
+                    found.device_id,
+                    found.class,
+                    found.subclass,
+                    found.prog_if,
+                    found.irq
+                );
+                for (idx, bar) in found.bars.iter().enumerate() {
+                    if let Some(bar) = bar {
+                        crate::println!("  BAR{}: {:#x} ({} bytes)", idx, bar.addr, bar.size);
+                    }
+                }
+                *slot = Some(found);
+                count += 1;
+            }
+            if function == 0 && header_type & HEADER_MULTI_FUNCTION == 0 {
+                break;
+            }
+        }
+    }
+}
+
+/// Returns the first device with the vendor and device id.
+#[allow(dead_code)] // No driver binds to a PCI device yet.
+pub fn find(vendor_id: u16, device_id: u16) -> Option<Device> {
+    DEVICES
+        .lock()
+        .iter()
+        .flatten()
+        .find(|device| device.vendor_id == vendor_id && device.device_id == device_id)
+        .copied()
+}
+
+/// Calls the handler on interrupts of the device and enables its irq.
+/// Returns false if the device does not exist or has no interrupt pin.
+#[allow(dead_code)] // No driver binds to a PCI device yet.
+pub fn set_handler(address: Address, handler: fn()) -> bool {
+    let mut devices = DEVICES.lock();
+    let Some(device) = devices
+        .iter_mut()
+        .flatten()
+        .find(|device| device.address == address)
+    else {
+        return false;
+    };
+    let Some(irq) = device.irq else {
+        return false;
+    };
+    device.handler = Some(handler);
+    // The irq is registered by the first device using it.
+    let shared = devices
+        .iter()
+        .flatten()
+        .filter(|device| device.irq == Some(irq) && device.handler.is_some())
+        .count()
+        > 1;
+    devices.unlock();
+    if !shared {
+        plic::register(irq, IRQ_PRIORITY, handle_interrupt);
+    }
+    true
+}
+
+/// Calls the handlers of all devices sharing the irq.
+fn handle_interrupt(irq: usize) {
+    let devices = DEVICES.lock();
+    let handlers = devices
+        .iter()
+        .flatten()
+        .filter(|device| device.irq == Some(irq))
+        .filter_map(|device| device.handler);
+    let mut pending = [None; MAX_DEVICES];
+    for (slot, handler) in pending.iter_mut().zip(handlers) {
+        *slot = Some(handler);
+    }
+    // The handlers may look up devices.
+    devices.unlock();
+    for handler in pending.into_iter().flatten() {
+        handler();
+    }
+}
+
+/// Reads the ids, assigns the memory BARs from the window, routes the interrupt pin and
+/// enables memory decoding and bus mastering.
+fn probe(address: Address, id: u32, window: &mut core::ops::Range<usize>) -> Device {
+    let class = address.read(CLASS);
+    let mut device = Device {
+        address,
+        vendor_id: id as u16,
+        device_id: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        prog_if: (class >> 8) as u8,
+        bars: [None; BARS],
+        irq: None,
+        handler: None,
+    };
+    // Disable decoding while the BARs are sized.
+    let command = address.read(COMMAND) & !(COMMAND_MEMORY | COMMAND_BUS_MASTER);
+    address.write(COMMAND, command);
+    let mut idx = 0;
+    while idx < BARS {
+        let bar = assign_bar(address, idx, window);
+        device.bars[idx] = bar.map(|(bar, _)| bar);
+        idx += match bar {
+            Some((_, true)) => 2,
+            _ => 1,
+        };
+    }
+    let pin = (address.read(INTERRUPT) >> 8) as u8;
+    if let Ok(pin) = Pin::try_from(pin as isize) {
+        let irq = pin.irq(address.device);
+        let interrupt = address.read(INTERRUPT) & !0xff;
+        address.write(INTERRUPT, interrupt | irq as u32);
+        device.irq = Some(irq);
+    }
+    address.write(COMMAND, command | COMMAND_MEMORY | COMMAND_BUS_MASTER);
+    device
+}
+
+/// Sizes the memory BAR and assigns it the next aligned address of the window.
+/// Returns the BAR and whether it is a 64-bit BAR. Unused and I/O BARs are [None].
+fn assign_bar(
+    address: Address,
+    idx: usize,
+    window: &mut core::ops::Range<usize>,
+) -> Option<(Bar, bool)> {
+    let offset = BAR0 + 4 * idx;
+    let original = address.read(offset);
+    if original & BAR_IO != 0 {
+        return None;
+    }
+    let is_64_bit = original & BAR_64_BIT != 0 && idx + 1 < BARS;
+    address.write(offset, u32::MAX);
+    let low = address.read(offset) & BAR_MEMORY_MASK;
+    let high = match is_64_bit {
+        true => {
+            address.write(offset + 4, u32::MAX);
+            address.read(offset + 4)
+        }
+        false => u32::MAX,
+    };
+    if low == 0 && (high == 0 || !is_64_bit) {
+        // Unused BARs ignore the write.
+        return None;
+    }
+    let mask = ((high as u64) << 32) | low as u64;
+    let size = (!mask).wrapping_add(1) as usize;
+    let addr = window.start.next_multiple_of(size);
+    if addr + size > window.end || (!is_64_bit && addr + size > u32::MAX as usize + 1) {
+        crate::println!("pci: no space for BAR {} of {} bytes", idx, size);
+        address.write(offset, 0);
+        return None;
+    }
+    window.start = addr + size;
+    address.write(offset, addr as u32);
+    if is_64_bit {
+        address.write(offset + 4, (addr >> 32) as u32);
+    }
+    let bar = Bar {
+        addr,
+        size,
+        prefetchable: original & BAR_PREFETCHABLE != 0,
+    };
+    Some((bar, is_64_bit))
+}
@@ -0,0 +1,208 @@
+//! platform -- Memory, harts and devices of the machine.
+//!
+//! Discovered from the device tree at boot. Without a device tree the layout of QEMU's `virt`
+//! machine with 128 MiB and a single hart is assumed.
+//! The memory layout of the kernel is fixed, the discovered memory only has to contain it.
+
+use core::ops::Range;
+
+use super::fdt::Fdt;
+use super::sync::Once;
+use super::virtio;
+
+static PLATFORM: Once<Platform> = Once::new();
+
+/// A memory mapped device.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Mmio {
+    pub addr: usize,
+    /// The PLIC interrupt request.
+    pub irq: usize,
+}
+
+/// The PCIe host bridge.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Pcie {
+    /// Base of the configuration space.
+    pub ecam: usize,
+    /// Window for memory BARs.
+    pub mmio: usize,
+    pub mmio_size: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Platform {
+    pub memory_start: usize,
+    pub memory_size: usize,
+    pub harts: usize,
+    pub uart: Mmio,
+    pub clint: usize,
+    pub plic: usize,
+    pub rtc: Mmio,
+    pub test_finisher: usize,
+    pub pcie: Option<Pcie>,
+    /// The virtio-mmio slots ordered by address.
+    pub virtio: [Option<Mmio>; virtio::SLOTS],
+}
+impl Platform {
+    const QEMU_VIRT: Platform = Platform {
+        memory_start: 0x8000_0000,
+        memory_size: 0x0800_0000,
+        harts: 1,
+        uart: Mmio {
+            addr: 0x1000_0000,
+            irq: 10,
+        },
+        clint: 0x0200_0000,
+        plic: 0x0c00_0000,
+        rtc: Mmio {
+            addr: 0x0010_1000,
+            irq: 11,
+        },
+        test_finisher: 0x0010_0000,
+        pcie: Some(Pcie {
+            ecam: 0x3000_0000,
+            mmio: 0x4000_0000,
+            mmio_size: 0x4000_0000,
+        }),
+        virtio: {
+            let mut virtio = [None; virtio::SLOTS];
+            let mut slot = 0;
+            while slot < virtio::SLOTS {
+                virtio[slot] = Some(Mmio {
+                    addr: 0x1000_1000 + slot * 0x1000,
+                    irq: 1 + slot,
+                });
+                slot += 1;
+            }
+            virtio
+        },
+    };
+
+    /// Replaces the defaults by the nodes found in the device tree.
+    fn discover(&mut self, fdt: Fdt) {
+        let mut harts = 0;
+        let mut pcie = None;
+        let mut virtio = [None; virtio::SLOTS];
+        for node in fdt.nodes() {
+            let Some((addr, size)) = node.reg() else {
+                continue;
+            };
+            if node.is_device_type(b"memory") {
+                self.memory_start = addr;
+                self.memory_size = size;
+            } else if node.is_device_type(b"cpu") {
+                harts += 1;
+            } else if node.is_compatible(b"ns16550a") {
+                self.uart = Mmio {
+                    addr,
+                    irq: node.interrupt().unwrap_or(self.uart.irq),
+                };
+            } else if node.is_compatible(b"riscv,clint0") {
+                self.clint = addr;
+            } else if node.is_compatible(b"riscv,plic0") {
+                self.plic = addr;
+            } else if node.is_compatible(b"google,goldfish-rtc") {
+                self.rtc = Mmio {
+                    addr,
+                    irq: node.interrupt().unwrap_or(self.rtc.irq),
+                };
+            } else if node.is_compatible(b"sifive,test0") {
+                self.test_finisher = addr;
+            } else if node.is_compatible(b"pci-host-ecam-generic") {
+                pcie = node
+                    .property(b"ranges")
+                    .and_then(mmio_range)
+                    .map(|(mmio, mmio_size)| Pcie {
+                        ecam: addr,
+                        mmio,
+                        mmio_size,
+                    });
+            } else if node.is_compatible(b"virtio,mmio") {
+                let irq = node.interrupt().unwrap_or(0);
+                insert_sorted(&mut virtio, Mmio { addr, irq });
+            }
+        }
+        self.harts = harts.max(1);
+        self.pcie = pcie;
+        self.virtio = virtio;
+    }
+}
+
+/// Parses the device tree at the address passed by the boot loader.
+/// Keeps the defaults if there is none.
+///
+/// # Safety
+///
+/// Must be called once before the drivers are initialized.
+pub unsafe fn init(fdt_addr: usize) {
+    PLATFORM.call_once(|| {
+        let mut platform = Platform::QEMU_VIRT;
+        match Fdt::from_addr(fdt_addr) {
+            Some(fdt) => {
+                platform.discover(fdt);
+                crate::println!(
+                    "platform: {} MiB memory at {:#x}, {} harts",
+                    platform.memory_size >> 20,
+                    platform.memory_start,
+                    platform.harts
+                );
+            }
+            None => {
+                crate::println!(
+                    "platform: no device tree at {:#x}, assuming QEMU virt",
+                    fdt_addr
+                );
+            }
+        }
+        platform
+    });
+}
+
+/// Panics if the range lies outside the discovered memory, e.g. with a too small `-m`.
+pub fn check_memory(name: &str, range: Range<usize>) {
+    let platform = get();
+    let memory = platform.memory_start..platform.memory_start + platform.memory_size;
+    if range.start < memory.start || range.end > memory.end {
+        panic!(
+            "The {} at {:#x?} lies outside the memory at {:#x?}, raise the memory size with -m",
+            name, range, memory
+        );
+    }
+}
+
+/// Returns the defaults of QEMU's `virt` machine before [init].
+pub fn get() -> &'static Platform {
+    PLATFORM.get().unwrap_or(&Platform::QEMU_VIRT)
+}
+
+/// Returns the CPU address and size of the 32-bit memory space of the `ranges` of a PCI host.
+/// Each range has 3 PCI address cells, 2 CPU address cells and 2 size cells.
+fn mmio_range(ranges: &[u8]) -> Option<(usize, usize)> {
+    /// Space code of the 32-bit memory space in the first PCI address cell.
+    const MEMORY_32_BIT: u8 = 0b10;
+    let cell = |range: &[u8], idx: usize| {
+        u32::from_be_bytes(range[idx * 4..idx * 4 + 4].try_into().unwrap()) as usize
+    };
+    ranges
+        .chunks_exact(7 * 4)
+        .find(|range| (range[0] & 0b11) == MEMORY_32_BIT)
+        .map(|range| {
+            (
+                cell(range, 3) << 32 | cell(range, 4),
+                cell(range, 5) << 32 | cell(range, 6),
+            )
+        })
+}
+
+/// Inserts the device in address order. Devices behind the last slot are dropped.
+fn insert_sorted(slots: &mut [Option<Mmio>], mmio: Mmio) {
+    let idx = slots
+        .iter()
+        .position(|slot| slot.is_none_or(|slot| slot.addr > mmio.addr))
+        .unwrap_or(slots.len());
+    if idx < slots.len() {
+        slots[idx..].rotate_right(1);
+        slots[idx] = Some(mmio);
+    }
+}
@@ -1,96 +1,165 @@
 //!  plic -- Platform-Level Interrupt Controller
+//!
+//! The registers are offsets to the base address of the platform.
+//! Drivers register a handler for the irq of their device with [register].
+//!
+//! [More Info](https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc#memory-map)
 
-use super::{binary_struct::BinaryStruct, memory_mapping::MemoryMapping};
-use enum_matching::EnumTryFrom;
+use super::sync::{self, RwLock};
+use super::{binary_struct::BinaryStruct, hart, memory_mapping::MemoryMapping, platform};
 
-/// Base-address for QEMU
-///
-/// [More Info](https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc#memory-map)
-const _PLIC_MEMORY_MAP_BASE: usize = 0x0c00_0000;
-
-/// Base address for the interrupt priorities.
-/// Starts at `_PLIC_MEMORY_MAP_BASE + 0x0000_0000` consisting of 32-bit registers.
+/// Offset of the interrupt priorities.
+/// Starts at `base + 0x0000_0000` consisting of 32-bit registers.
 /// Priorities are unsigned u32.
 /// 0 means "never interrupt".
 /// Max priority is platform specific.
 /// Note that *0x0000_0000* does not have an interrupt source since interrupt 0 does not exist.
 ///
 /// [More Info](https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc#interrupt-priorities)
-pub const PRIORITY_BASE_ADDR: usize = 0x0c00_0000;
+const PRIORITY: usize = 0x0000_0000;
 
-/// Base address for enabling interrupt sources.
-/// Starts at `_PLIC_MEMORY_MAP_BASE + 0x0000_2000`.
+/// Offset for enabling interrupt sources.
+/// Starts at `base + 0x0000_2000`.
 /// 1-bit for enabling the interrupt source with ID = bit position.
-/// Continuous block (0-1023) for 15872 contexts.
+/// Continuous block (0-1023) for 15872 contexts, incremented by 0x80 for each context.
 ///
 /// [More Info](https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc#interrupt-enables)
-pub const ENABLE_ADDR: usize = 0x0c00_2000;
+const ENABLE: usize = 0x0000_2000;
 
-/// Base address for setting an interrupt priority threshold.
-/// Starts at `_PLIC_MEMORY_MAP_BASE + 0x0020_0000`.
+/// Offset for setting an interrupt priority threshold.
+/// Starts at `base + 0x0020_0000`.
 /// Incremented by 0x1000 for each context.
 /// PLIC ignorers all interrupts with priority less than or equal to the given threshold.
 /// Set individually for all 15872 contexts.
 ///
 /// [More Info](https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc#priority-thresholds)
-pub const THRESHOLD_ADDR: usize = 0x0c20_0000;
+const THRESHOLD: usize = 0x0020_0000;
 
-/// Base address for the interrupt claim and completion registers.
-/// Starts at `_PLIC_MEMORY_MAP_BASE + 0x0020_0004`.
+/// Offset of the interrupt claim and completion registers.
+/// Starts at `base + 0x0020_0004`.
 /// Incremented by 0x1000 for each context.
 /// If an interrupt is handled by a service after receiving an interrupt notification the interrupt has to be claimed from the PLIC.
 /// PLIC returns the interrupt ID to the service.
 /// Returns 0 if no interrupt is pending.
 ///
 /// [More Info](https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc#interrupt-claim-process)
-pub const CLAIM_COMP_ADDR: usize = 0x0c20_0004;
+const CLAIM_COMP: usize = 0x0020_0004;
 
-/// Interrupt request.
-#[derive(EnumTryFrom)]
-pub enum Irq {
-    Uart = 10,
-}
+/// Number of interrupt sources with a handler table entry.
+/// QEMU's `virt` machine has 96 sources, source 0 does not exist.
+const SOURCES: usize = 128;
+
+/// Called with the irq after it was claimed. The irq is completed when the handler returns.
+pub type Handler = fn(usize);
 
+/// The handlers and priorities of the irqs.
+static HANDLERS: RwLock<[Option<(Handler, u32)>; SOURCES]> = RwLock::new([None; SOURCES]);
+
+/// Disables all sources and clears the thresholds of the contexts of all harts.
 pub fn init() {
-    unsafe {
-        let uart_priority_addr = get_priority_addr(Irq::Uart);
-        MemoryMapping::new(uart_priority_addr).write(5);
-        // Enable in context.
-        let mut enable_c0 = [BinaryStruct::from(0u32); 32];
-        let (uart_idx, uart_bit) = group_idx_and_bit_pos(Irq::Uart);
-        enable_c0[uart_idx].at(uart_bit, true);
-        let enable_addr = get_enable_addr(uart_idx);
-        MemoryMapping::new(enable_addr).write(enable_c0[uart_idx].into_inner());
-        // Set thresholds for context.
-        MemoryMapping::new(THRESHOLD_ADDR).write(0u32);
+    let harts = platform::get().harts.min(hart::MAX_HARTS);
+    for hart in 0..harts {
+        for idx in 0..SOURCES / 32 {
+            unsafe { MemoryMapping::new(get_enable_addr(hart, idx)).write(0u32) };
+        }
+        set_threshold(hart, 0);
     }
 }
 
-pub fn read_claim() -> Irq {
-    unsafe {
-        let claim: u32 = MemoryMapping::new(CLAIM_COMP_ADDR).read();
-        let claim = claim as usize;
-        Irq::try_from(claim as isize)
-            .unwrap_or_else(|_| panic!("Unknown plic interrupt request: {}", claim))
+/// Calls the handler on interrupts of the irq and enables it with the priority.
+/// Interrupts are taken by the context of the boot hart.
+///
+/// Panics if the irq does not exist or already has a handler.
+pub fn register(irq: usize, priority: u32, handler: Handler) {
+    let mut handlers = HANDLERS.write();
+    match handlers.get_mut(irq) {
+        Some(slot @ None) if irq != 0 => *slot = Some((handler, priority)),
+        Some(Some(_)) => panic!("The plic irq {} already has a handler", irq),
+        _ => panic!("Invalid plic irq: {}", irq),
     }
+    handlers.unlock();
+    unsafe { MemoryMapping::new(get_priority_addr(irq)).write(priority) };
+    enable(irq, hart::BOOT_HART);
+}
+
+/// Enables the irq in the context of the hart.
+pub fn enable(irq: usize, hart: usize) {
+    set_enabled(irq, hart, true);
 }
 
-pub fn write_complete(irq: Irq) {
+/// Disables the irq in the context of the hart.
+pub fn disable(irq: usize, hart: usize) {
+    set_enabled(irq, hart, false);
+}
+
+/// Masks all interrupts of the context of the hart with a priority less than or equal to the threshold.
+pub fn set_threshold(hart: usize, threshold: u32) {
+    unsafe { MemoryMapping::new(get_context_addr(hart, THRESHOLD)).write(threshold) };
+}
+
+/// Returns the threshold of the context of the hart.
+pub fn threshold(hart: usize) -> u32 {
+    unsafe { MemoryMapping::new(get_context_addr(hart, THRESHOLD)).read() }
+}
+
+/// Claims the pending irq of the context of the hart, calls its handler and completes it.
+/// A claim of 0 is spurious, e.g. the irq was already claimed by another context.
+///
+/// The handler runs with interrupts enabled and the threshold raised to the priority of the irq.
+/// Only timer, software and higher priority external interrupts preempt it.
+pub fn handle_interrupt() {
+    let hart = hart::id();
+    let claim = MemoryMapping::new(get_context_addr(hart, CLAIM_COMP));
+    let irq = unsafe { claim.read() } as usize;
+    if irq == 0 {
+        return;
+    }
+    let handler = HANDLERS.read().get(irq).copied().flatten();
+    match handler {
+        Some((handler, priority)) => {
+            let threshold = threshold(hart);
+            set_threshold(hart, priority.max(threshold));
+            sync::with_interrupts(|| handler(irq));
+            set_threshold(hart, threshold);
+        }
+        None => {
+            crate::println!("plic: no handler for irq {}, disabling it", irq);
+            disable(irq, hart);
+        }
+    }
+    unsafe { claim.write(irq as u32) };
+}
+
+fn set_enabled(irq: usize, hart: usize, enabled: bool) {
     unsafe {
-        MemoryMapping::new(CLAIM_COMP_ADDR).write(irq as u32);
+        let (idx, bit) = group_idx_and_bit_pos(irq);
+        let enable = MemoryMapping::new(get_enable_addr(hart, idx));
+        let mut enable_bits = BinaryStruct::<u32>::from(enable.read());
+        enable_bits.at(bit, enabled);
+        enable.write(enable_bits.into_inner());
     }
 }
 
-fn get_priority_addr(irq: Irq) -> usize {
-    PRIORITY_BASE_ADDR + 4 * irq as usize
+/// Returns the machine-mode context of the hart.
+/// QEMU's `virt` machine has a machine-mode and a supervisor-mode context per hart.
+fn context(hart: usize) -> usize {
+    2 * hart
+}
+
+fn get_priority_addr(irq: usize) -> usize {
+    platform::get().plic + PRIORITY + 4 * irq
 }
 
 /// Returns the (group index, bit position) of an irq if every bit is used as an id for an irq.
-fn group_idx_and_bit_pos(irq: Irq) -> (usize, usize) {
-    let irq = irq as usize;
+fn group_idx_and_bit_pos(irq: usize) -> (usize, usize) {
     (irq / 32, (irq % 32))
 }
 
-fn get_enable_addr(idx: usize) -> usize {
-    ENABLE_ADDR + 4 * idx
+fn get_enable_addr(hart: usize, idx: usize) -> usize {
+    platform::get().plic + ENABLE + 0x80 * context(hart) + 4 * idx
+}
+
+/// Returns the address of the threshold or claim register of the context of the hart.
+fn get_context_addr(hart: usize, offset: usize) -> usize {
+    platform::get().plic + offset + 0x1000 * context(hart)
 }
@@ -1,6 +1,6 @@
 //! pmp -- Physical Memory Protection
 
-use riscv_utils::write_machine_reg;
+use riscv_utils::{write_machine_reg, SHM_SIZE};
 
 use super::binary_struct::Byte;
 
@@ -24,15 +24,45 @@ pub fn init() {
     }
 }
 
-pub fn switch_prog_pmp(idx: usize) {
+/// Index of the first pmp entry for shared memory regions.
+const SHM_ENTRY: usize = 5;
+/// Number of shared memory regions a user prog can access at once.
+pub const SHM_ENTRIES: usize = 2;
+
+/// Grants the user prog access to its memory and the shared memory regions at the addresses.
+pub fn switch_prog_pmp(idx: usize, shm: &[Option<usize>; SHM_ENTRIES]) {
     unsafe {
         let prog_index = idx + 2; // device and kernel offset.
         let mut pmpcfg0 = Pmpcfg::new();
         pmpcfg0.set_rwx(prog_index);
+        for (i, addr) in shm.iter().enumerate() {
+            if let Some(addr) = addr {
+                let entry = SHM_ENTRY + i;
+                write_pmp_addr(entry, napot_addr(*addr, SHM_SIZE));
+                pmpcfg0.set_rw_napot(entry);
+            }
+        }
         write_machine_reg!(pmpcfg0.to_usize() => "pmpcfg0");
     }
 }
 
+/// Encodes a naturally aligned power-of-two region.
+fn napot_addr(addr: usize, size: usize) -> usize {
+    (addr | (size / 2 - 1)) >> 2
+}
+
+unsafe fn write_pmp_addr(entry: usize, pmp_addr: usize) {
+    match entry {
+        5 => {
+            write_machine_reg!(pmp_addr => "pmpaddr5");
+        }
+        6 => {
+            write_machine_reg!(pmp_addr => "pmpaddr6");
+        }
+        _ => panic!("Pmp entry: {} is not available for shared memory", entry),
+    }
+}
+
 #[repr(C)]
 struct Pmpcfg([Byte; 8]);
 impl Pmpcfg {
@@ -50,6 +80,13 @@ impl Pmpcfg {
         reg.at(2, true); // X
         reg.at(3, true); // A - top of range
     }
+    fn set_rw_napot(&mut self, at: usize) {
+        let reg = &mut self.0[at];
+        reg.at(0, true); // R
+        reg.at(1, true); // W
+        reg.at(3, true); // A - naturally aligned power-of-two
+        reg.at(4, true);
+    }
     fn new() -> Self {
         let bytes = [Byte::from(0); 8];
         Pmpcfg(bytes)
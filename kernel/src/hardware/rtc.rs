@@ -0,0 +1,56 @@
+//! rtc -- Goldfish real-time clock.
+//!
+//! Provides the wall time in nanoseconds since the Unix epoch and an alarm interrupt.
+//!
+//! [More Info](https://android.googlesource.com/platform/external/qemu/+/master/docs/GOLDFISH-VIRTUAL-HARDWARE.TXT)
+
+use super::{memory_mapping::MemoryMapping, platform, plic};
+use crate::{alarm, deferred};
+
+/// Reading the low word latches the high word.
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+/// Writing the low word sets the alarm. The high word has to be written first.
+const ALARM_LOW: usize = 0x08;
+const ALARM_HIGH: usize = 0x0c;
+const IRQ_ENABLED: usize = 0x10;
+const CLEAR_ALARM: usize = 0x14;
+const CLEAR_INTERRUPT: usize = 0x1c;
+const IRQ_PRIORITY: u32 = 3;
+
+pub fn init() {
+    write(IRQ_ENABLED, 1);
+    plic::register(platform::get().rtc.irq, IRQ_PRIORITY, handle_interrupt);
+}
+
+/// Returns the wall time in nanoseconds since the Unix epoch.
+pub fn now() -> u64 {
+    let low = read(TIME_LOW) as u64;
+    let high = read(TIME_HIGH) as u64;
+    (high << 32) | low
+}
+
+/// Raises an interrupt at the wall time. Replaces the previous alarm.
+/// The interrupt is raised immediately if the time has already passed.
+pub fn set_alarm(time: u64) {
+    write(ALARM_HIGH, (time >> 32) as u32);
+    write(ALARM_LOW, time as u32);
+}
+
+pub fn clear_alarm() {
+    write(CLEAR_ALARM, 1);
+}
+
+/// Acknowledges the alarm interrupt. Waking the user progs waiting for it is deferred.
+fn handle_interrupt(_irq: usize) {
+    write(CLEAR_INTERRUPT, 1);
+    deferred::queue(alarm::wake_expired);
+}
+
+fn read(offset: usize) -> u32 {
+    unsafe { MemoryMapping::new(platform::get().rtc.addr + offset).read() }
+}
+
+fn write(offset: usize, val: u32) {
+    unsafe { MemoryMapping::new(platform::get().rtc.addr + offset).write(val) }
+}
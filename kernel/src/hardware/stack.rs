@@ -1,7 +1,18 @@
 //! A wrapper to read and write registers stored on the stack when context switching.
 
+use riscv_utils::{Message, MSG_WORDS};
+
 use super::memory_mapping::MemoryMapping;
 
+/// Size of the registers saved by a trap, see `exception.S`.
+pub const FRAME_SIZE: usize = 256;
+/// Index of the stack pointer, it is stored after making room for the registers.
+const SP_IDX: usize = 1;
+/// Index of the thread pointer `tp`.
+const TP_IDX: usize = 3;
+/// Index of the register `a1` holding the first word of an ipc message.
+const MSG_IDX: usize = 10;
+
 #[repr(C)]
 pub struct Stack(MemoryMapping<[usize; 32]>, [usize; 32]);
 
@@ -11,15 +22,40 @@ impl Stack {
         let stack = mem_stack.read();
         Stack(mem_stack, stack)
     }
+    /// Returns an empty stack frame at `sp`. Restoring it sets the stack pointer above the frame.
+    pub unsafe fn empty(sp: usize) -> Self {
+        let mut stack = [0; 32];
+        stack[SP_IDX] = sp;
+        Stack(MemoryMapping::new(sp), stack)
+    }
     pub fn a0(&self) -> usize {
         self.1[9]
     }
     pub fn a1(&self) -> usize {
         self.1[10]
     }
+    pub fn a2(&self) -> usize {
+        self.1[11]
+    }
     pub fn a7(&self) -> usize {
         self.1[16]
     }
+    /// Returns the ipc message stored in the registers `a1` to `a4`.
+    pub fn msg(&self) -> Message {
+        let mut msg = Message::default();
+        msg.0.copy_from_slice(&self.1[MSG_IDX..MSG_IDX + MSG_WORDS]);
+        msg
+    }
+    /// Sets the ipc message stored in the registers `a1` to `a4`.
+    pub fn set_msg(&mut self, msg: Message) {
+        self.1[MSG_IDX..MSG_IDX + MSG_WORDS].copy_from_slice(&msg.0);
+    }
+    pub fn set_tp(&mut self, tp: usize) {
+        self.1[TP_IDX] = tp;
+    }
+    pub fn set_a0(&mut self, a0: usize) {
+        self.1[9] = a0;
+    }
     /// Sets the return value.
     pub fn set_ret(&mut self, ret: usize) {
         self.1[9] = ret;
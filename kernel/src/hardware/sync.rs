@@ -1,39 +1,72 @@
-//! A simple wrapper protecting its data using an atomic bool.
+//! Synchronization primitives usable from interrupt handlers and across harts.
 //!
-//! The [Mutex] or [RwLock] is not available in core Rust.
+//! Machine-mode interrupts are disabled while a lock is held. The owning hart and the call site
+//! of a lock are recorded to report recursive locking and deadlocks.
+//!
+//! The `Mutex`, `RwLock` or `LazyLock` of std are not available in core Rust.
 
 use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
 use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::panic::Location;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+
+use super::hart;
+
+/// Owner of an unlocked lock or of a lock held by readers.
+const NO_OWNER: usize = usize::MAX;
+/// Number of failed attempts after which waiting for a lock is considered a deadlock.
+const MAX_SPINS: usize = 1 << 28;
+/// `mie` bit of `mstatus`.
+const MSTATUS_MIE: usize = 1 << 3;
 
 pub struct Protected<T> {
-    mutex: AtomicBool,
+    /// Id of the hart holding the lock.
+    owner: AtomicUsize,
+    /// Call site of the lock holding it.
+    location: AtomicPtr<Location<'static>>,
     data: UnsafeCell<T>,
 }
 impl<T> Protected<T> {
     pub const fn new(data: T) -> Self {
         Protected {
-            mutex: AtomicBool::new(false),
+            owner: AtomicUsize::new(NO_OWNER),
+            location: AtomicPtr::new(null_mut()),
             data: UnsafeCell::new(data),
         }
     }
     /// Calling lock will block until acquiring the lock.
-    /// This can lead to a deadlock if the lock is not unlocked before/ while trying to lock,
-    /// e.g. when calling lock twice without unlocking it.
+    /// Interrupts of the hart are disabled until it is unlocked.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the hart already holds the lock or spins for too long, e.g. on a deadlock.
     ///
     /// # Unlocking
     ///
     /// The lock is unlocked when the [ProtectedData] is dropped, e.g. when it leaves it's scope.
+    #[track_caller]
     pub fn lock(&self) -> ProtectedData<'_, T> {
-        while self
-            .mutex
-            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Acquire)
-            .is_err()
-        {}
-        ProtectedData { protected: self }
+        let interrupts = disable_interrupts();
+        let mut spins = Spins::new();
+        while let Err(owner) = self.owner.compare_exchange_weak(
+            NO_OWNER,
+            spins.hart,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        ) {
+            spins.wait(owner, call_site(&self.location));
+        }
+        set_call_site(&self.location);
+        ProtectedData {
+            protected: self,
+            interrupts,
+        }
     }
     fn unlock(&self) {
-        self.mutex.store(false, Ordering::Release);
+        self.location.store(null_mut(), Ordering::Relaxed);
+        self.owner.store(NO_OWNER, Ordering::Release);
     }
     /// Required when a lock cannot be unlocked by dropping the [ProtectedData].
     /// Possible use-cases are an `mret` while still holding the lock or when printing a kernel panic.
@@ -41,9 +74,11 @@ impl<T> Protected<T> {
         self.unlock();
     }
 }
-/// Dropping the [ProtectedData] will unlock the [Protected].
+/// Dropping the [ProtectedData] will unlock the [Protected] and restore the interrupts.
 pub struct ProtectedData<'a, T> {
     protected: &'a Protected<T>,
+    /// Whether interrupts were enabled before locking.
+    interrupts: bool,
 }
 impl<T> ProtectedData<'_, T> {
     pub fn unlock(self) {}
@@ -51,6 +86,7 @@ impl<T> ProtectedData<'_, T> {
 impl<T> Drop for ProtectedData<'_, T> {
     fn drop(&mut self) {
         self.protected.unlock();
+        restore_interrupts(self.interrupts);
     }
 }
 impl<T> Deref for ProtectedData<'_, T> {
@@ -65,3 +101,348 @@ impl<T> DerefMut for ProtectedData<'_, T> {
     }
 }
 unsafe impl<T> Sync for Protected<T> {}
+
+/// A lock allowing many readers or a single writer at a time.
+pub struct RwLock<T> {
+    /// Number of readers or [RwLock::WRITER] while written.
+    state: AtomicUsize,
+    /// Id of the hart holding the write lock.
+    writer: AtomicUsize,
+    /// Call site of the write lock holding it.
+    location: AtomicPtr<Location<'static>>,
+    data: UnsafeCell<T>,
+}
+impl<T> RwLock<T> {
+    const WRITER: usize = usize::MAX;
+
+    pub const fn new(data: T) -> Self {
+        RwLock {
+            state: AtomicUsize::new(0),
+            writer: AtomicUsize::new(NO_OWNER),
+            location: AtomicPtr::new(null_mut()),
+            data: UnsafeCell::new(data),
+        }
+    }
+    /// Blocks while the lock is written. Readers do not block each other.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the hart holds the write lock or spins for too long.
+    #[track_caller]
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        let interrupts = disable_interrupts();
+        let mut spins = Spins::new();
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state != Self::WRITER
+                && self
+                    .state
+                    .compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                break;
+            }
+            spins.wait(
+                self.writer.load(Ordering::Relaxed),
+                call_site(&self.location),
+            );
+        }
+        RwLockReadGuard {
+            lock: self,
+            interrupts,
+        }
+    }
+    /// Blocks until there are no readers and no writer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the hart holds the write lock or spins for too long.
+    #[track_caller]
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let interrupts = disable_interrupts();
+        let mut spins = Spins::new();
+        while self
+            .state
+            .compare_exchange_weak(0, Self::WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            spins.wait(
+                self.writer.load(Ordering::Relaxed),
+                call_site(&self.location),
+            );
+        }
+        self.writer.store(spins.hart, Ordering::Relaxed);
+        set_call_site(&self.location);
+        RwLockWriteGuard {
+            lock: self,
+            interrupts,
+        }
+    }
+    fn unlock_write(&self) {
+        self.location.store(null_mut(), Ordering::Relaxed);
+        self.writer.store(NO_OWNER, Ordering::Relaxed);
+        self.state.store(0, Ordering::Release);
+    }
+    /// Required when the write lock cannot be unlocked by dropping the [RwLockWriteGuard],
+    /// e.g. on an `mret` while still holding it.
+    pub unsafe fn unsafe_unlock_write(&self) {
+        self.unlock_write();
+    }
+}
+unsafe impl<T> Sync for RwLock<T> {}
+
+/// Dropping the [RwLockReadGuard] will release the read lock and restore the interrupts.
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+    /// Whether interrupts were enabled before locking.
+    interrupts: bool,
+}
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(1, Ordering::Release);
+        restore_interrupts(self.interrupts);
+    }
+}
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+/// Dropping the [RwLockWriteGuard] will release the write lock and restore the interrupts.
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+    /// Whether interrupts were enabled before locking.
+    interrupts: bool,
+}
+impl<T> RwLockWriteGuard<'_, T> {
+    pub fn unlock(self) {}
+}
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock_write();
+        restore_interrupts(self.interrupts);
+    }
+}
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+/// A value initialized once at runtime, e.g. from the device tree.
+pub struct Once<T> {
+    state: AtomicU8,
+    data: UnsafeCell<MaybeUninit<T>>,
+}
+impl<T> Once<T> {
+    const INCOMPLETE: u8 = 0;
+    const RUNNING: u8 = 1;
+    const COMPLETE: u8 = 2;
+
+    pub const fn new() -> Self {
+        Once {
+            state: AtomicU8::new(Self::INCOMPLETE),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+    /// Initializes the value on the first call and returns it.
+    /// Concurrent callers wait until the value is initialized.
+    #[track_caller]
+    pub fn call_once(&self, init: impl FnOnce() -> T) -> &T {
+        match self.state.compare_exchange(
+            Self::INCOMPLETE,
+            Self::RUNNING,
+            Ordering::Acquire,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => {
+                unsafe { (*self.data.get()).write(init()) };
+                self.state.store(Self::COMPLETE, Ordering::Release);
+            }
+            Err(_) => {
+                let mut spins = Spins::new();
+                while self.state.load(Ordering::Acquire) != Self::COMPLETE {
+                    spins.wait(NO_OWNER, CallSite(None));
+                }
+            }
+        }
+        unsafe { (*self.data.get()).assume_init_ref() }
+    }
+    /// Returns [None] before the value is initialized.
+    pub fn get(&self) -> Option<&T> {
+        match self.state.load(Ordering::Acquire) {
+            Self::COMPLETE => Some(unsafe { (*self.data.get()).assume_init_ref() }),
+            _ => None,
+        }
+    }
+}
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+
+/// A value initialized on its first use.
+pub struct Lazy<T, F = fn() -> T> {
+    once: Once<T>,
+    init: F,
+}
+impl<T, F: Fn() -> T> Lazy<T, F> {
+    pub const fn new(init: F) -> Self {
+        Lazy {
+            once: Once::new(),
+            init,
+        }
+    }
+}
+impl<T, F: Fn() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+    #[track_caller]
+    fn deref(&self) -> &T {
+        self.once.call_once(&self.init)
+    }
+}
+unsafe impl<T: Send + Sync, F: Sync> Sync for Lazy<T, F> {}
+
+/// A lock-free queue with a single producer and a single consumer, e.g. an interrupt handler
+/// passing data to a system call.
+pub struct SpscQueue<T, const N: usize> {
+    buffer: [UnsafeCell<MaybeUninit<T>>; N],
+    /// Number of pushed values. Only written by the producer.
+    head: AtomicUsize,
+    /// Number of popped values. Only written by the consumer.
+    tail: AtomicUsize,
+}
+impl<T: Copy, const N: usize> SpscQueue<T, N> {
+    pub const fn new() -> Self {
+        SpscQueue {
+            buffer: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+    /// Appends the value. Returns false if the queue is full. Must only be called by the producer.
+    pub fn push(&self, val: T) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        if head.wrapping_sub(self.tail.load(Ordering::Acquire)) == N {
+            return false;
+        }
+        unsafe { (*self.buffer[head % N].get()).write(val) };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        true
+    }
+    /// Removes the oldest value. Must only be called by the consumer.
+    pub fn pop(&self) -> Option<T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+        let val = unsafe { (*self.buffer[tail % N].get()).assume_init_read() };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(val)
+    }
+    /// Removes all values. Must only be called by the consumer.
+    pub fn clear(&self) {
+        let head = self.head.load(Ordering::Acquire);
+        self.tail.store(head, Ordering::Release);
+    }
+}
+unsafe impl<T: Send, const N: usize> Sync for SpscQueue<T, N> {}
+
+/// Counts the attempts of a hart waiting for a lock.
+struct Spins {
+    hart: usize,
+    count: usize,
+}
+impl Spins {
+    fn new() -> Self {
+        Spins {
+            hart: hart::id(),
+            count: 0,
+        }
+    }
+    /// Panics if the lock is held by the waiting hart or after too many attempts.
+    #[track_caller]
+    fn wait(&mut self, owner: usize, location: CallSite) {
+        if owner == self.hart {
+            panic!(
+                "Recursive lock at {} on hart {}, already locked at {}",
+                Location::caller(),
+                self.hart,
+                location
+            );
+        }
+        self.count += 1;
+        if self.count == MAX_SPINS {
+            panic!(
+                "Deadlock at {} on hart {}, locked by {} at {}",
+                Location::caller(),
+                self.hart,
+                Owner(owner),
+                location
+            );
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// The hart holding a lock.
+struct Owner(usize);
+impl core::fmt::Display for Owner {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            NO_OWNER => write!(f, "readers"),
+            hart => write!(f, "hart {}", hart),
+        }
+    }
+}
+
+/// The call site of a lock. It is unknown if the lock was released in the meantime.
+struct CallSite(Option<&'static Location<'static>>);
+impl core::fmt::Display for CallSite {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Some(location) => write!(f, "{}", location),
+            None => write!(f, "unknown"),
+        }
+    }
+}
+
+fn call_site(location: &AtomicPtr<Location<'static>>) -> CallSite {
+    CallSite(unsafe { location.load(Ordering::Relaxed).as_ref() })
+}
+
+#[track_caller]
+fn set_call_site(location: &AtomicPtr<Location<'static>>) {
+    let caller: *const Location = Location::caller();
+    location.store(caller.cast_mut(), Ordering::Relaxed);
+}
+
+/// Runs the closure with machine-mode interrupts enabled, e.g. to let a long-running interrupt
+/// handler be preempted. Must not be called while holding a lock.
+pub fn with_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let mstatus: usize;
+    unsafe { core::arch::asm!("csrrs {}, mstatus, {}", out(reg) mstatus, in(reg) MSTATUS_MIE) };
+    let ret = f();
+    if mstatus & MSTATUS_MIE == 0 {
+        disable_interrupts();
+    }
+    ret
+}
+
+/// Disables machine-mode interrupts. Returns whether they were enabled.
+fn disable_interrupts() -> bool {
+    let mstatus: usize;
+    unsafe { core::arch::asm!("csrrc {}, mstatus, {}", out(reg) mstatus, in(reg) MSTATUS_MIE) };
+    mstatus & MSTATUS_MIE != 0
+}
+
+fn restore_interrupts(interrupts: bool) {
+    if interrupts {
+        unsafe { core::arch::asm!("csrs mstatus, {}", in(reg) MSTATUS_MIE) };
+    }
+}
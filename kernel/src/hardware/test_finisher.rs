@@ -0,0 +1,32 @@
+//! test_finisher -- SiFive test finisher of QEMU.
+//!
+//! Writing to the device stops or resets the machine. QEMU exits with the status code on failure.
+//!
+//! [More Info](https://github.com/qemu/qemu/blob/master/hw/misc/sifive_test.c)
+
+use super::{memory_mapping::MemoryMapping, platform};
+
+const FAIL: u32 = 0x3333;
+const PASS: u32 = 0x5555;
+const RESET: u32 = 0x7777;
+
+/// Powers off the machine. QEMU exits with the code, 0 means success.
+pub fn shutdown(code: u16) -> ! {
+    let val = match code {
+        0 => PASS,
+        code => FAIL | (code as u32) << 16,
+    };
+    write(val)
+}
+
+pub fn reboot() -> ! {
+    write(RESET)
+}
+
+fn write(val: u32) -> ! {
+    unsafe { MemoryMapping::new(platform::get().test_finisher).write(val) };
+    // Panicking here could recurse as shutdown is called by the panic handler.
+    loop {
+        unsafe { core::arch::asm!("wfi") };
+    }
+}
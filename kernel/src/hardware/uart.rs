@@ -3,22 +3,30 @@
 use core::fmt::Debug;
 use core::ops::{Div, Rem};
 
-use crate::scheduler::Prog;
+use crate::deferred;
+use crate::scheduler::{self, Prog};
 
 use super::binary_struct::{BinaryStruct, Byte, MaxDigits};
 use super::memory_mapping::MemoryMapping;
-use super::ring_buffer::RingBuffer;
-use super::sync::Protected;
+use super::platform;
+use super::plic;
+use super::sync::{Protected, SpscQueue};
 
+/// Used until [init] reads the address from the platform.
 const BASE_ADDR: usize = 0x1000_0000;
+const IRQ_PRIORITY: u32 = 5;
 
-static READ_CHAR: Protected<RingBuffer<char>> = Protected::new(RingBuffer::new('X'));
+/// Filled by the interrupt handler, emptied by the prog which opened 'read'.
+/// Its threads read on any hart, so the consumer side only runs while holding [UART].
+static READ_CHAR: SpscQueue<char, 16> = SpscQueue::new();
 
 pub static UART: Protected<Uart> = Protected::new(Uart::new());
 
 pub fn init() {
     unsafe {
-        let mem_ier = &UART.lock().reg.ier_dlm;
+        let mut uart = UART.lock();
+        uart.reg = UartRegister::new(platform::get().uart.addr);
+        let mem_ier = &uart.reg.ier_dlm;
         let mut ier = BinaryStruct::from(0);
         ier.at(0, true); // receive interrupt
         ier.at(1, false); // transmit interrupt
@@ -26,6 +34,32 @@ pub fn init() {
         ier.at(3, false); // receiver transmit status interrupt
         mem_ier.write(ier);
     }
+    plic::register(platform::get().uart.irq, IRQ_PRIORITY, handle_interrupt);
+}
+
+/// Buffers the received char. Waking the user prog blocked on reading it is deferred.
+fn handle_interrupt(_irq: usize) {
+    if get_interrupt_cause() != Interrupt::ReceivedDataRdy {
+        panic!(
+            "Unsupported UART interrupt with code: {:?}",
+            get_interrupt_cause()
+        );
+    }
+    if unsafe { read_char_to_buffer() }.is_some() {
+        deferred::queue(wake_reader);
+    }
+}
+
+/// Wakes the thread of the user prog blocked on reading. The blocked read is repeated and takes
+/// the char from the buffer.
+fn wake_reader() {
+    let Some(uart_prog) = UART.lock().open_user_prog else {
+        return;
+    };
+    if let Some(reader) = uart_prog.find_blocked_thread(scheduler::Reason::Uart) {
+        reader.set_rdy();
+        scheduler::switch(reader);
+    }
 }
 
 pub fn get_interrupt_cause() -> Interrupt {
@@ -63,14 +97,14 @@ pub unsafe fn read_char_to_buffer() -> Option<Prog> {
     let char = uart.read_char();
     let open_user_prog = uart.open_user_prog;
     uart.unlock();
-    if open_user_prog.is_some() {
-        READ_CHAR.lock().write(char);
+    if open_user_prog.is_some() && !READ_CHAR.push(char) {
+        crate::println!("\nuart: read buffer full, {:?} lost", char);
     }
     open_user_prog
 }
 pub fn get_char() -> Option<char> {
-    let char = READ_CHAR.lock().read();
-    char
+    let _uart = UART.lock();
+    READ_CHAR.pop()
 }
 pub fn print_char(char: char) {
     UART.lock().print_char(char as u8);
@@ -83,7 +117,7 @@ pub fn open(user_prog: Prog) -> bool {
         return open == user_prog;
     }
     uart.open_user_prog = Some(user_prog);
-    READ_CHAR.lock().clear();
+    READ_CHAR.clear();
     true
 }
 
@@ -0,0 +1,429 @@
+//! virtio -- Virtual I/O devices over the MMIO transport.
+//!
+//! QEMU's `virt` machine provides eight virtio-mmio slots. Empty slots report the device id 0.
+//! The slots are taken from the platform in address order.
+//! Both the legacy (version 1) and the modern (version 2) interface are supported.
+//!
+//! [More Info](https://docs.oasis-open.org/virtio/virtio/v1.1/virtio-v1.1.html)
+
+use core::mem::size_of;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{fence, Ordering};
+
+use enum_matching::EnumTryFrom;
+
+use super::memory_mapping::MemoryMapping;
+use super::platform;
+use super::plic;
+use super::sync::Protected;
+
+pub const SLOTS: usize = 8;
+const IRQ_PRIORITY: u32 = 4;
+
+/// "virt" in little endian.
+const MAGIC_VALUE: u32 = 0x7472_6976;
+/// Number of descriptors in a virtqueue.
+pub const QUEUE_SIZE: usize = 8;
+/// Alignment of the used ring for the legacy interface.
+const QUEUE_ALIGN: usize = 4096;
+
+// Register offsets.
+const MAGIC: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const DEVICE_FEATURES: usize = 0x010;
+const DEVICE_FEATURES_SEL: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+const GUEST_PAGE_SIZE: usize = 0x028;
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+const QUEUE_ALIGN_REG: usize = 0x03c;
+const QUEUE_PFN: usize = 0x040;
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+const QUEUE_DESC_LOW: usize = 0x080;
+const QUEUE_DESC_HIGH: usize = 0x084;
+const QUEUE_DRIVER_LOW: usize = 0x090;
+const QUEUE_DRIVER_HIGH: usize = 0x094;
+const QUEUE_DEVICE_LOW: usize = 0x0a0;
+const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+const CONFIG: usize = 0x100;
+
+// Device status bits.
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+const STATUS_FAILED: u32 = 128;
+
+/// Feature bit required for the modern interface.
+pub const FEATURE_VERSION_1: u64 = 1 << 32;
+
+/// The devices found while probing.
+static DEVICES: Protected<[Option<Device>; SLOTS]> = Protected::new([None; SLOTS]);
+
+#[derive(Clone, Copy)]
+struct Device {
+    transport: Transport,
+    /// The PLIC interrupt request.
+    irq: usize,
+    /// Called after the interrupt is acknowledged.
+    handler: Option<fn()>,
+}
+
+#[derive(EnumTryFrom, Clone, Copy, PartialEq, Debug)]
+pub enum DeviceId {
+    Network = 1,
+    Block = 2,
+    Console = 3,
+    Entropy = 4,
+    Balloon = 5,
+    Scsi = 8,
+    Gpu = 16,
+    Input = 18,
+    Socket = 19,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The device did not accept the negotiated features.
+    FeaturesRejected,
+    /// The queue is not available or already in use.
+    QueueUnavailable,
+}
+
+/// Probes all slots and reports the found devices.
+pub fn init() {
+    let mut devices = DEVICES.lock();
+    let slots = platform::get().virtio;
+    for (slot, (device, mmio)) in devices.iter_mut().zip(slots).enumerate() {
+        let Some(mmio) = mmio else {
+            continue;
+        };
+        let mut transport = Transport::new(mmio.addr);
+        if !transport.is_valid() {
+            continue;
+        }
+        match transport.device_id() {
+            Some(id) => {
+                crate::println!(
+                    "virtio-mmio slot {}: {:?} device (version {})",
+                    slot,
+                    id,
+                    transport.version
+                );
+            }
+            None => {
+                crate::println!(
+                    "virtio-mmio slot {}: unknown device {}",
+                    slot,
+                    transport.raw_device_id()
+                );
+            }
+        }
+        *device = Some(Device {
+            transport,
+            irq: mmio.irq,
+            handler: None,
+        });
+    }
+}
+
+/// Returns the transport and slot of the first device with the id.
+pub fn find(id: DeviceId) -> Option<(usize, Transport)> {
+    DEVICES
+        .lock()
+        .iter()
+        .enumerate()
+        .find_map(|(slot, device)| {
+            device
+                .filter(|device| device.transport.device_id() == Some(id))
+                .map(|device| (slot, device.transport))
+        })
+}
+
+/// Calls the handler on interrupts of the device in the slot and enables its irq.
+pub fn set_handler(slot: usize, handler: fn()) {
+    let mut devices = DEVICES.lock();
+    let device = devices[slot]
+        .as_mut()
+        .unwrap_or_else(|| panic!("No virtio device in slot {}", slot));
+    device.handler = Some(handler);
+    let irq = device.irq;
+    devices.unlock();
+    plic::register(irq, IRQ_PRIORITY, handle_interrupt);
+}
+
+/// Acknowledges the interrupt of the device and calls its handler.
+fn handle_interrupt(irq: usize) {
+    let devices = DEVICES.lock();
+    let Some(device) = devices.iter().flatten().find(|device| device.irq == irq) else {
+        panic!("Interrupt from empty virtio-mmio slot: {}", irq);
+    };
+    device.transport.ack_interrupt();
+    let handler = device.handler;
+    devices.unlock();
+    if let Some(handler) = handler {
+        handler();
+    }
+}
+
+/// The MMIO registers of a virtio device.
+#[derive(Clone, Copy)]
+pub struct Transport {
+    base: usize,
+    version: u32,
+}
+impl Transport {
+    const fn new(base: usize) -> Self {
+        Transport { base, version: 0 }
+    }
+    /// Checks the magic value and reads the version. Empty slots are not valid.
+    fn is_valid(&mut self) -> bool {
+        self.version = self.read(VERSION);
+        self.read(MAGIC) == MAGIC_VALUE && self.raw_device_id() != 0
+    }
+    fn raw_device_id(&self) -> u32 {
+        self.read(DEVICE_ID)
+    }
+    pub fn device_id(&self) -> Option<DeviceId> {
+        DeviceId::try_from(self.raw_device_id() as isize).ok()
+    }
+    /// Resets the device and negotiates the features supported by both the device and the driver.
+    /// Returns the negotiated features.
+    pub fn init(&self, driver_features: u64) -> Result<u64, Error> {
+        self.write(STATUS, 0);
+        self.set_status(STATUS_ACKNOWLEDGE);
+        self.set_status(STATUS_DRIVER);
+        let mut features = self.device_features() & driver_features;
+        if self.version >= 2 {
+            features |= FEATURE_VERSION_1;
+        }
+        self.write(DRIVER_FEATURES_SEL, 0);
+        self.write(DRIVER_FEATURES, features as u32);
+        self.write(DRIVER_FEATURES_SEL, 1);
+        self.write(DRIVER_FEATURES, (features >> 32) as u32);
+        if self.version >= 2 {
+            self.set_status(STATUS_FEATURES_OK);
+            if self.read(STATUS) & STATUS_FEATURES_OK == 0 {
+                self.set_status(STATUS_FAILED);
+                return Err(Error::FeaturesRejected);
+            }
+        } else {
+            self.write(GUEST_PAGE_SIZE, QUEUE_ALIGN as u32);
+        }
+        Ok(features)
+    }
+    /// Hands the memory of the virtqueue to the device.
+    pub fn setup_queue(&self, idx: u32, queue: &VirtQueue) -> Result<(), Error> {
+        self.write(QUEUE_SEL, idx);
+        let max = self.read(QUEUE_NUM_MAX) as usize;
+        if max < QUEUE_SIZE {
+            return Err(Error::QueueUnavailable);
+        }
+        self.write(QUEUE_NUM, QUEUE_SIZE as u32);
+        if self.version >= 2 {
+            if self.read(QUEUE_READY) != 0 {
+                return Err(Error::QueueUnavailable);
+            }
+            let desc = &queue.desc as *const _ as u64;
+            let avail = &queue.avail as *const _ as u64;
+            let used = &queue.used as *const _ as u64;
+            self.write(QUEUE_DESC_LOW, desc as u32);
+            self.write(QUEUE_DESC_HIGH, (desc >> 32) as u32);
+            self.write(QUEUE_DRIVER_LOW, avail as u32);
+            self.write(QUEUE_DRIVER_HIGH, (avail >> 32) as u32);
+            self.write(QUEUE_DEVICE_LOW, used as u32);
+            self.write(QUEUE_DEVICE_HIGH, (used >> 32) as u32);
+            self.write(QUEUE_READY, 1);
+        } else {
+            if self.read(QUEUE_PFN) != 0 {
+                return Err(Error::QueueUnavailable);
+            }
+            self.write(QUEUE_ALIGN_REG, QUEUE_ALIGN as u32);
+            let pfn = queue as *const _ as usize / QUEUE_ALIGN;
+            self.write(QUEUE_PFN, pfn as u32);
+        }
+        Ok(())
+    }
+    /// Tells the device that the driver is ready.
+    pub fn driver_ok(&self) {
+        self.set_status(STATUS_DRIVER_OK);
+    }
+    /// Tells the device that new buffers are available in the queue.
+    pub fn notify(&self, idx: u32) {
+        self.write(QUEUE_NOTIFY, idx);
+    }
+    /// Reads the device specific configuration at the offset.
+    pub fn config<T>(&self, offset: usize) -> T {
+        unsafe { MemoryMapping::new(self.base + CONFIG + offset).read() }
+    }
+    fn ack_interrupt(&self) {
+        let status = self.read(INTERRUPT_STATUS);
+        self.write(INTERRUPT_ACK, status);
+    }
+    fn device_features(&self) -> u64 {
+        self.write(DEVICE_FEATURES_SEL, 0);
+        let low = self.read(DEVICE_FEATURES) as u64;
+        self.write(DEVICE_FEATURES_SEL, 1);
+        let high = self.read(DEVICE_FEATURES) as u64;
+        high << 32 | low
+    }
+    fn set_status(&self, status: u32) {
+        self.write(STATUS, self.read(STATUS) | status);
+    }
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { MemoryMapping::new(self.base + offset).read() }
+    }
+    fn write(&self, offset: usize, val: u32) {
+        unsafe { MemoryMapping::new(self.base + offset).write(val) }
+    }
+}
+
+/// The descriptor is continued in `next`.
+const DESC_F_NEXT: u16 = 1;
+/// The buffer is written by the device.
+const DESC_F_WRITE: u16 = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct Avail {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+    used_event: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+#[repr(C)]
+struct Used {
+    flags: u16,
+    idx: u16,
+    ring: [UsedElem; QUEUE_SIZE],
+    avail_event: u16,
+}
+
+/// Padding to align the used ring for the legacy interface.
+const USED_PADDING: usize =
+    QUEUE_ALIGN - size_of::<[Descriptor; QUEUE_SIZE]>() - size_of::<Avail>();
+
+/// A buffer of a descriptor chain.
+pub struct Buffer {
+    pub addr: usize,
+    pub len: usize,
+    /// The buffer is written by the device.
+    pub write: bool,
+}
+
+/// A split virtqueue with the layout required by the legacy interface.
+#[repr(C, align(4096))]
+pub struct VirtQueue {
+    desc: [Descriptor; QUEUE_SIZE],
+    avail: Avail,
+    _padding: [u8; USED_PADDING],
+    used: Used,
+    free: [bool; QUEUE_SIZE],
+    last_used_idx: u16,
+}
+impl VirtQueue {
+    pub const fn new() -> Self {
+        VirtQueue {
+            desc: [Descriptor {
+                addr: 0,
+                len: 0,
+                flags: 0,
+                next: 0,
+            }; QUEUE_SIZE],
+            avail: Avail {
+                flags: 0,
+                idx: 0,
+                ring: [0; QUEUE_SIZE],
+                used_event: 0,
+            },
+            _padding: [0; USED_PADDING],
+            used: Used {
+                flags: 0,
+                idx: 0,
+                ring: [UsedElem { id: 0, len: 0 }; QUEUE_SIZE],
+                avail_event: 0,
+            },
+            free: [true; QUEUE_SIZE],
+            last_used_idx: 0,
+        }
+    }
+    /// Chains the buffers and makes them available to the device.
+    /// Returns the id of the chain or [None] if not enough descriptors are free.
+    pub fn push(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        if buffers.is_empty() || self.free.iter().filter(|free| **free).count() < buffers.len() {
+            return None;
+        }
+        let mut head = None;
+        let mut prev: Option<usize> = None;
+        for buffer in buffers {
+            let idx = self.free.iter().position(|free| *free)?;
+            self.free[idx] = false;
+            self.desc[idx] = Descriptor {
+                addr: buffer.addr as u64,
+                len: buffer.len as u32,
+                flags: if buffer.write { DESC_F_WRITE } else { 0 },
+                next: 0,
+            };
+            match prev {
+                Some(prev) => {
+                    self.desc[prev].flags |= DESC_F_NEXT;
+                    self.desc[prev].next = idx as u16;
+                }
+                None => head = Some(idx as u16),
+            }
+            prev = Some(idx);
+        }
+        let head = head?;
+        let avail_idx = unsafe { addr_of!(self.avail.idx).read_volatile() };
+        self.avail.ring[avail_idx as usize % QUEUE_SIZE] = head;
+        fence(Ordering::SeqCst);
+        unsafe { addr_of_mut!(self.avail.idx).write_volatile(avail_idx.wrapping_add(1)) };
+        fence(Ordering::SeqCst);
+        Some(head)
+    }
+    /// Takes the next chain used by the device and frees its descriptors.
+    /// Returns the id of the chain and the number of bytes written by the device.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        fence(Ordering::SeqCst);
+        let used_idx = unsafe { addr_of!(self.used.idx).read_volatile() };
+        if used_idx == self.last_used_idx {
+            return None;
+        }
+        let elem = unsafe {
+            addr_of!(self.used.ring[self.last_used_idx as usize % QUEUE_SIZE]).read_volatile()
+        };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        let mut idx = elem.id as usize;
+        loop {
+            self.free[idx] = true;
+            if self.desc[idx].flags & DESC_F_NEXT == 0 {
+                break;
+            }
+            idx = self.desc[idx].next as usize;
+        }
+        Some((elem.id as u16, elem.len))
+    }
+}
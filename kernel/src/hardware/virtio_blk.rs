@@ -0,0 +1,190 @@
+//! virtio_blk -- Virtio block device driver.
+//!
+//! Each request is a chain of three descriptors: the header, the sector data and the status.
+//!
+//! [More Info](https://docs.oasis-open.org/virtio/virtio/v1.1/cs01/virtio-v1.1-cs01.html#x1-2390002)
+
+use core::mem::size_of;
+use core::ptr::addr_of;
+
+use super::sync::Protected;
+use super::virtio::{self, Buffer, DeviceId, Transport, VirtQueue, QUEUE_SIZE};
+use crate::block::{self, BlockDevice, Error, Op, SECTOR_SIZE};
+use crate::deferred;
+
+/// Number of requests fitting into the virtqueue at once.
+const REQUESTS: usize = QUEUE_SIZE / 3;
+const TYPE_IN: u32 = 0;
+const TYPE_OUT: u32 = 1;
+const STATUS_OK: u8 = 0;
+/// Offset of the capacity in sectors in the device configuration.
+const CONFIG_CAPACITY: usize = 0;
+
+static VIRTIO_BLK: VirtioBlk = VirtioBlk(Protected::new(Blk::new()));
+
+pub fn init() {
+    let Some((slot, transport)) = virtio::find(DeviceId::Block) else {
+        return;
+    };
+    let mut blk = VIRTIO_BLK.0.lock();
+    transport
+        .init(0)
+        .and_then(|_| transport.setup_queue(0, &blk.queue))
+        .unwrap_or_else(|err| panic!("Failed to init virtio-blk in slot {}: {:?}", slot, err));
+    transport.driver_ok();
+    blk.capacity = transport.config(CONFIG_CAPACITY);
+    blk.transport = Some(transport);
+    blk.unlock();
+    virtio::set_handler(slot, handle_interrupt);
+    crate::println!("virtio-blk: {} sectors", VIRTIO_BLK.capacity());
+}
+
+/// Returns the block device if one was found while probing.
+pub fn device() -> Option<&'static dyn BlockDevice> {
+    if VIRTIO_BLK.0.lock().transport.is_some() {
+        return Some(&VIRTIO_BLK);
+    }
+    None
+}
+
+/// Finishing the requests is deferred, the interrupt is acknowledged by the transport.
+fn handle_interrupt() {
+    deferred::queue(finish_requests);
+}
+
+/// Finishes the requests used by the device and wakes the waiting user progs.
+fn finish_requests() {
+    VIRTIO_BLK.0.lock().finish_used();
+    for id in 0..REQUESTS {
+        block::wake(&VIRTIO_BLK, id);
+    }
+}
+
+pub struct VirtioBlk(Protected<Blk>);
+impl BlockDevice for VirtioBlk {
+    fn capacity(&self) -> u64 {
+        self.0.lock().capacity
+    }
+    unsafe fn submit(&self, op: Op, sector: u64, buf: *mut u8) -> Result<usize, Error> {
+        let mut blk = self.0.lock();
+        let transport = blk.transport.expect("virtio-blk is not initialized");
+        if sector >= blk.capacity {
+            return Err(Error::OutOfRange);
+        }
+        let id = blk
+            .requests
+            .iter()
+            .position(|request| request.state == State::Free)
+            .ok_or(Error::Busy)?;
+        let request = &mut blk.requests[id];
+        request.header = Header {
+            kind: match op {
+                Op::Read => TYPE_IN,
+                Op::Write => TYPE_OUT,
+            },
+            reserved: 0,
+            sector,
+        };
+        request.status = u8::MAX;
+        let buffers = [
+            Buffer {
+                addr: &request.header as *const _ as usize,
+                len: size_of::<Header>(),
+                write: false,
+            },
+            Buffer {
+                addr: buf as usize,
+                len: SECTOR_SIZE,
+                write: op == Op::Read,
+            },
+            Buffer {
+                addr: &request.status as *const _ as usize,
+                len: 1,
+                write: true,
+            },
+        ];
+        let head = blk.queue.push(&buffers).ok_or(Error::Busy)?;
+        blk.requests[id].state = State::Pending(head);
+        transport.notify(0);
+        Ok(id)
+    }
+    fn poll(&self, id: usize) -> Option<Result<(), Error>> {
+        let mut blk = self.0.lock();
+        blk.finish_used();
+        let request = blk.requests.get_mut(id)?;
+        match request.state {
+            State::Free => Some(Err(Error::InvalidRequest)),
+            State::Pending(_) => None,
+            State::Done(result) => {
+                request.state = State::Free;
+                Some(result)
+            }
+        }
+    }
+}
+
+struct Blk {
+    transport: Option<Transport>,
+    queue: VirtQueue,
+    requests: [Request; REQUESTS],
+    capacity: u64,
+}
+impl Blk {
+    const fn new() -> Self {
+        Blk {
+            transport: None,
+            queue: VirtQueue::new(),
+            requests: [Request {
+                header: Header {
+                    kind: 0,
+                    reserved: 0,
+                    sector: 0,
+                },
+                status: 0,
+                state: State::Free,
+            }; REQUESTS],
+            capacity: 0,
+        }
+    }
+    /// Marks the requests used by the device as done.
+    fn finish_used(&mut self) {
+        while let Some((head, _)) = self.queue.pop_used() {
+            let request = self
+                .requests
+                .iter_mut()
+                .find(|request| request.state == State::Pending(head))
+                .unwrap_or_else(|| panic!("virtio-blk used an unknown descriptor: {}", head));
+            let status = unsafe { addr_of!(request.status).read_volatile() };
+            request.state = State::Done(match status {
+                STATUS_OK => Ok(()),
+                _ => Err(Error::Io),
+            });
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Header {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Free,
+    /// Submitted with the head descriptor.
+    Pending(u16),
+    Done(Result<(), Error>),
+}
+
+#[derive(Clone, Copy)]
+struct Request {
+    /// Read by the device.
+    #[allow(dead_code)]
+    header: Header,
+    /// Written by the device.
+    status: u8,
+    state: State,
+}
@@ -0,0 +1,65 @@
+//! virtio_rng -- Virtio entropy device driver.
+//!
+//! The device fills buffers of the request queue with entropy. The driver waits by polling.
+//!
+//! [More Info](https://docs.oasis-open.org/virtio/virtio/v1.1/cs01/virtio-v1.1-cs01.html#x1-2700004)
+
+use super::sync::Protected;
+use super::virtio::{self, Buffer, DeviceId, Transport, VirtQueue};
+
+static VIRTIO_RNG: Protected<Rng> = Protected::new(Rng::new());
+
+pub fn init() {
+    let Some((slot, transport)) = virtio::find(DeviceId::Entropy) else {
+        return;
+    };
+    let mut rng = VIRTIO_RNG.lock();
+    transport
+        .init(0)
+        .and_then(|_| transport.setup_queue(0, &rng.queue))
+        .unwrap_or_else(|err| panic!("Failed to init virtio-rng in slot {}: {:?}", slot, err));
+    transport.driver_ok();
+    rng.transport = Some(transport);
+}
+
+/// Fills the buffer with entropy of the device. Returns false if there is no device.
+pub fn fill(buf: &mut [u8]) -> bool {
+    let mut rng = VIRTIO_RNG.lock();
+    let Some(transport) = rng.transport else {
+        return false;
+    };
+    let mut filled = 0;
+    while filled < buf.len() {
+        let rest = &mut buf[filled..];
+        let buffer = Buffer {
+            addr: rest.as_mut_ptr() as usize,
+            len: rest.len(),
+            write: true,
+        };
+        rng.queue
+            .push(&[buffer])
+            .expect("The virtio-rng queue is full");
+        transport.notify(0);
+        let len = loop {
+            if let Some((_, len)) = rng.queue.pop_used() {
+                break len;
+            }
+            core::hint::spin_loop();
+        };
+        filled += len as usize;
+    }
+    true
+}
+
+struct Rng {
+    transport: Option<Transport>,
+    queue: VirtQueue,
+}
+impl Rng {
+    const fn new() -> Self {
+        Rng {
+            transport: None,
+            queue: VirtQueue::new(),
+        }
+    }
+}
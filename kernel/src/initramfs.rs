@@ -0,0 +1,135 @@
+//! initramfs -- Read-only file system of a ustar archive.
+//!
+//! The archive is loaded next to the kernel by QEMU (see `run.sh`).
+//! Paths are relative to the root of the archive and limited to the 100 byte name field,
+//! the ustar prefix field is not supported.
+//!
+//! [More Info](https://www.gnu.org/software/tar/manual/html_node/Standard.html)
+
+use riscv_utils::{FileKind, Stat};
+
+/// Start of the archive. Behind the memory of the user progs.
+const ADDR: usize = 0x8040_0000;
+const BLOCK_SIZE: usize = 512;
+const NAME: core::ops::Range<usize> = 0..100;
+const SIZE: core::ops::Range<usize> = 124..136;
+const TYPE_FLAG: usize = 156;
+const MAGIC: core::ops::Range<usize> = 257..262;
+/// The first header of the archive. Its size is only known after parsing it.
+pub const FIRST_HEADER: core::ops::Range<usize> = ADDR..ADDR + BLOCK_SIZE;
+
+/// A file or directory of the archive.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Entry {
+    /// Path without leading or trailing slashes. Empty for the root directory.
+    path: &'static [u8],
+    data: &'static [u8],
+    kind: FileKind,
+}
+impl Entry {
+    const ROOT: Entry = Entry {
+        path: b"",
+        data: &[],
+        kind: FileKind::Dir,
+    };
+    pub fn data(&self) -> &'static [u8] {
+        self.data
+    }
+    pub fn stat(&self) -> Stat {
+        Stat {
+            size: self.data.len(),
+            kind: self.kind,
+        }
+    }
+    /// Returns the last component of the path.
+    pub fn file_name(&self) -> &'static [u8] {
+        match self.path.iter().rposition(|&byte| byte == b'/') {
+            Some(idx) => &self.path[idx + 1..],
+            None => self.path,
+        }
+    }
+    /// Returns the entry of the directory at the index. Returns [None] past the last entry.
+    pub fn child(&self, idx: usize) -> Option<Entry> {
+        if self.kind != FileKind::Dir {
+            return None;
+        }
+        entries()
+            .filter(|entry| !entry.path.is_empty() && parent(entry.path) == self.path)
+            .nth(idx)
+    }
+}
+
+/// Returns the entry at the path.
+pub fn find(path: &[u8]) -> Option<Entry> {
+    let path = trim(path);
+    if path.is_empty() {
+        return Some(Entry::ROOT);
+    }
+    entries().find(|entry| entry.path == path)
+}
+
+fn entries() -> Entries {
+    Entries { addr: ADDR }
+}
+
+struct Entries {
+    addr: usize,
+}
+impl Iterator for Entries {
+    type Item = Entry;
+    fn next(&mut self) -> Option<Entry> {
+        loop {
+            let header = unsafe { core::slice::from_raw_parts(self.addr as *const u8, BLOCK_SIZE) };
+            // The archive ends with zero blocks. Memory without an archive is zeroed as well.
+            if &header[MAGIC] != b"ustar" {
+                return None;
+            }
+            let size = parse_octal(&header[SIZE]);
+            let data =
+                unsafe { core::slice::from_raw_parts((self.addr + BLOCK_SIZE) as *const u8, size) };
+            self.addr += BLOCK_SIZE + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+            let kind = match header[TYPE_FLAG] {
+                b'0' | 0 => FileKind::File,
+                b'5' => FileKind::Dir,
+                _ => continue, // Links and special files are skipped.
+            };
+            let name = &header[NAME];
+            let len = name
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap_or(name.len());
+            return Some(Entry {
+                path: trim(&name[..len]),
+                data,
+                kind,
+            });
+        }
+    }
+}
+
+/// Removes a leading `./` or `/` and a trailing `/`. The root directory `.` becomes empty.
+fn trim(path: &[u8]) -> &[u8] {
+    let path = path.strip_prefix(b"./").unwrap_or(path);
+    let path = path.strip_prefix(b"/").unwrap_or(path);
+    let path = path.strip_suffix(b"/").unwrap_or(path);
+    if path == b"." {
+        return b"";
+    }
+    path
+}
+
+fn parent(path: &[u8]) -> &[u8] {
+    match path.iter().rposition(|&byte| byte == b'/') {
+        Some(idx) => &path[..idx],
+        None => b"",
+    }
+}
+
+/// Parses the octal number of a header field. Stops at the first non octal digit.
+fn parse_octal(field: &[u8]) -> usize {
+    field
+        .iter()
+        .skip_while(|&&byte| byte == b' ')
+        .take_while(|byte| (b'0'..=b'7').contains(byte))
+        .fold(0, |num, byte| num * 8 + (byte - b'0') as usize)
+}
@@ -0,0 +1,108 @@
+//! ipc -- Synchronous message passing between user progs.
+//!
+//! Messages are carried in the registers `a1` to `a4`, the pid in `a0`.
+//! A sender blocks until the receiver takes the message. It fails if the receiver exits meanwhile.
+//! The message is copied directly between the saved registers of both user progs.
+//! Any thread of a user prog can receive the messages sent to its pid.
+
+use riscv_utils::*;
+
+use crate::{
+    hardware::stack::Stack,
+    scheduler::{self, Prog, Reason},
+    sys_call::sys_yield,
+    user_prog,
+};
+
+/// Sends the message of the current user prog to the user prog with the pid.
+/// If `call` is set the current user prog waits for the reply afterwards.
+///
+/// Returns [None] if the return value was already written or the user prog is blocked.
+pub fn send(pid: usize, call: bool) -> Option<usize> {
+    let cur = scheduler::cur();
+    cur.increment_mepc();
+    let receiver = match find(pid) {
+        Ok(receiver) if receiver == cur => return Some(to_ret(Err(SysCallError::InvalidArgument))),
+        Ok(receiver) => receiver,
+        Err(err) => return Some(to_ret(Err(err))),
+    };
+    let receiving = receiver
+        .find_blocked_thread(Reason::Receive(None))
+        .or_else(|| receiver.find_blocked_thread(Reason::Receive(Some(cur.id()))));
+    if let Some(receiver) = receiving {
+        let msg = unsafe { Stack::new(cur.sp()).msg() };
+        deliver(cur, receiver, msg);
+        if !call {
+            return Some(0);
+        }
+        cur.set_blocked(Reason::Receive(Some(receiver.id())));
+    } else if call {
+        cur.set_blocked(Reason::Call(receiver.id()));
+    } else {
+        cur.set_blocked(Reason::Send(receiver.id()));
+    }
+    sys_yield();
+    None
+}
+
+/// Receives a message from any user prog.
+/// Takes the message of a blocked sender or blocks the current user prog until a message arrives.
+///
+/// Always returns [None] as the return value is written with the message.
+pub fn receive() -> Option<usize> {
+    let cur = scheduler::cur();
+    cur.increment_mepc();
+    let sender = scheduler::find_blocked(Reason::Send(cur.id()))
+        .or_else(|| scheduler::find_blocked(Reason::Call(cur.id())));
+    if let Some(sender) = sender {
+        let call = sender.is_blocked(Reason::Call(cur.id()));
+        unsafe {
+            let mut sender_stack = Stack::new(sender.sp());
+            deliver(sender, cur, sender_stack.msg());
+            sender_stack.set_ret(0);
+            sender_stack.write();
+        }
+        if call {
+            sender.set_blocked(Reason::Receive(Some(cur.id())));
+        } else {
+            sender.set_rdy();
+        }
+        return None;
+    }
+    cur.set_blocked(Reason::Receive(None));
+    sys_yield();
+    None
+}
+
+/// Wakes the user progs sending to the user prog with the id or waiting for its reply with
+/// [SysCallError::NoSuchProg]. Called by the reaper after the user prog ended.
+pub fn cancel(id: user_prog::Id) {
+    let waiting = |reason| match reason {
+        Reason::Send(receiver) | Reason::Call(receiver) | Reason::Receive(Some(receiver)) => {
+            receiver == id
+        }
+        _ => false,
+    };
+    while let Some((sender, _)) = scheduler::find_blocked_by(waiting) {
+        sender.set_rdy_with_ret(to_ret(Err(SysCallError::NoSuchProg)));
+    }
+}
+
+fn find(pid: usize) -> Result<Prog, SysCallError> {
+    user_prog::Id::try_from(pid as isize)
+        .ok()
+        .and_then(scheduler::find)
+        .ok_or(SysCallError::NoSuchProg)
+}
+
+/// Writes the pid of the sender and the message into the saved registers of the receiver.
+/// The receiver is rdy afterwards.
+fn deliver(sender: Prog, receiver: Prog, msg: Message) {
+    unsafe {
+        let mut stack = Stack::new(receiver.sp());
+        stack.set_ret(sender.id() as usize);
+        stack.set_msg(msg);
+        stack.write();
+    }
+    receiver.set_rdy();
+}
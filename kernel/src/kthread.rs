@@ -0,0 +1,86 @@
+//! kthread -- Kernel threads.
+//!
+//! Kernel threads run in machine mode with interrupts enabled on their own stack and are scheduled
+//! like user progs. Traps save their registers on their stack. They give up the hart with an
+//! `ecall`, e.g. to yield or after parking.
+
+use core::cell::UnsafeCell;
+
+use crate::{
+    hardware::stack::{Stack, FRAME_SIZE},
+    scheduler,
+};
+
+pub const MAX_THREADS: usize = 4;
+const STACK_SIZE: usize = 16 * 1024;
+
+#[repr(align(16))]
+struct ThreadStack(UnsafeCell<[u8; STACK_SIZE]>);
+unsafe impl Sync for ThreadStack {}
+
+/// The stack of a kernel thread is indexed like the kernel thread.
+static STACKS: [ThreadStack; MAX_THREADS] =
+    [const { ThreadStack(UnsafeCell::new([0; STACK_SIZE])) }; MAX_THREADS];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct KThread(usize);
+impl KThread {
+    pub fn new(idx: usize) -> Self {
+        KThread(idx)
+    }
+    pub fn idx(&self) -> usize {
+        self.0
+    }
+    /// Makes the kernel thread rdy if it is parked. Otherwise its next [park] returns immediately.
+    pub fn unpark(&self) {
+        scheduler::unpark_kernel_thread(*self);
+    }
+}
+
+/// Runs the function in a new kernel thread. The kernel thread exits when the function returns.
+pub fn spawn(name: &'static str, entry: fn()) -> KThread {
+    let thread = scheduler::add_kernel_thread(start as *const () as usize, entry as usize);
+    crate::println!("kthread {}: {}", thread.idx(), name);
+    thread
+}
+
+/// Stores the registers of a starting kernel thread with `arg` in `a0` on its stack.
+/// Returns the stack pointer. Called by the scheduler after picking the index.
+pub fn init_stack(idx: usize, arg: usize) -> usize {
+    let sp = STACKS[idx].0.get() as usize + STACK_SIZE - FRAME_SIZE;
+    unsafe {
+        let mut stack = Stack::empty(sp);
+        stack.set_a0(arg);
+        stack.write();
+    }
+    sp
+}
+
+/// Returns the current kernel thread of the hart.
+pub fn current() -> KThread {
+    scheduler::cur_kernel_thread()
+}
+
+/// Lets the hart run other user progs and kernel threads. Must not be called while holding a lock.
+pub fn yield_now() {
+    unsafe { core::arch::asm!("ecall") };
+}
+
+/// Blocks the current kernel thread until it is unparked.
+pub fn park() {
+    scheduler::park_kernel_thread(current());
+    yield_now();
+}
+
+fn exit() -> ! {
+    scheduler::exit_kernel_thread(current());
+    yield_now();
+    unreachable!("Exited kernel thread was scheduled");
+}
+
+/// The first function of every kernel thread.
+extern "C" fn start(entry: usize) -> ! {
+    let entry: fn() = unsafe { core::mem::transmute(entry) };
+    entry();
+    exit();
+}
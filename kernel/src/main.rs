@@ -1,27 +1,49 @@
 #![no_std]
 #![no_main]
 
+mod alarm;
 mod asm;
+mod block;
+mod deferred;
+mod dev;
+mod elf;
+mod event;
 mod exception_handler;
+mod fat32;
+mod fd;
+mod futex;
 mod hardware;
+mod initramfs;
+mod ipc;
+mod kthread;
 mod macros;
+mod name;
 mod panic_handler;
+mod random;
+mod reaper;
 mod scheduler;
+mod semaphore;
 mod setup;
+mod shm;
 mod sys_call;
+mod thread;
 mod user_prog;
+mod vfs;
 
 pub(crate) use macros::*;
 
-fn _shutdown() {
-    todo!("Implement shutdown.")
-}
-
+/// Entered from `boot.S` by every hart with the hart id in `a0` and the address of the device tree in `a1`.
 #[no_mangle]
-unsafe extern "C" fn kernel_setup() {
-    setup::setup();
-    // switch to user mode (configured in mstatus) and jump to address in mepc CSR -> main().
-    let user1 = scheduler::init_prog(user_prog::USER1);
+unsafe extern "C" fn kernel_setup(hart_id: usize, fdt_addr: usize) -> ! {
+    if hart_id != hardware::hart::BOOT_HART {
+        hardware::hart::wait_for_release();
+        setup::setup_hart();
+        exception_handler::run_hart();
+    }
+    setup::setup(fdt_addr);
+    scheduler::init_prog(user_prog::USER1);
     scheduler::init_prog(user_prog::USER2);
-    scheduler::boot_prog(user1);
+    hardware::hart::release();
+    // switch to user mode (configured in mstatus) and jump to address in mepc CSR -> main().
+    exception_handler::run_hart();
 }
@@ -0,0 +1,51 @@
+//! Names of kernel objects shared between user progs, e.g. shared memory regions or semaphores.
+
+use riscv_utils::SysCallError;
+
+pub const NAME_LEN: usize = 16;
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Name {
+    bytes: [u8; NAME_LEN],
+    len: usize,
+}
+impl Name {
+    /// Returns [SysCallError::InvalidArgument] if the name is empty or too long.
+    pub fn new(name: &[u8]) -> Result<Self, SysCallError> {
+        if name.is_empty() || name.len() > NAME_LEN {
+            return Err(SysCallError::InvalidArgument);
+        }
+        let mut bytes = [0; NAME_LEN];
+        bytes[..name.len()].copy_from_slice(name);
+        Ok(Name {
+            bytes,
+            len: name.len(),
+        })
+    }
+}
+
+/// A kernel object identified by a [Name].
+pub trait Named {
+    fn name(&self) -> Name;
+}
+
+/// Returns the index of the object with the name.
+/// The object is created in a free slot if it does not exist.
+pub fn find_or_create<T: Named>(
+    list: &mut [Option<T>],
+    name: Name,
+    create: impl FnOnce(usize) -> T,
+) -> Result<usize, SysCallError> {
+    if let Some(idx) = list
+        .iter()
+        .position(|object| object.as_ref().is_some_and(|object| object.name() == name))
+    {
+        return Ok(idx);
+    }
+    let idx = list
+        .iter()
+        .position(Option::is_none)
+        .ok_or(SysCallError::NoSpace)?;
+    list[idx] = Some(create(idx));
+    Ok(idx)
+}
@@ -1,9 +1,12 @@
-use crate::hardware::uart::UART;
+use crate::hardware::{test_finisher, uart::UART};
 use crate::print;
 
+/// Exit code of QEMU after a kernel panic.
+const PANIC_EXIT_CODE: u16 = 1;
+
 #[panic_handler]
 unsafe fn panic(info: &core::panic::PanicInfo) -> ! {
     UART.unsafe_unlock();
     print!("\n\n\n### System Crash ###\n{}", info);
-    loop {}
+    test_finisher::shutdown(PANIC_EXIT_CODE)
 }
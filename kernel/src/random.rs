@@ -0,0 +1,100 @@
+//! random -- Cryptographically secure pseudo random numbers.
+//!
+//! A ChaCha20 generator seeded by the virtio entropy device. The key is replaced by output of the
+//! generator after every request (fast key erasure), so earlier output cannot be reconstructed.
+//! Without an entropy device a fixed seed is used and the output is deterministic.
+//!
+//! [More Info](https://datatracker.ietf.org/doc/html/rfc8439#section-2.3)
+
+use crate::hardware::sync::{Lazy, Protected};
+use crate::hardware::virtio_rng;
+
+const SEED_SIZE: usize = 32;
+const BLOCK_SIZE: usize = 64;
+/// "expand 32-byte k"
+const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+const FALLBACK_SEED: [u8; SEED_SIZE] = *b"riscv-os fixed random seed 0001!";
+
+/// Seeded on first use, after the entropy device is initialized.
+static RNG: Lazy<Protected<ChaCha20>> = Lazy::new(|| Protected::new(ChaCha20::new(seed())));
+
+/// Returns entropy of the virtio entropy device if available.
+fn seed() -> [u8; SEED_SIZE] {
+    let mut seed = [0; SEED_SIZE];
+    if virtio_rng::fill(&mut seed) {
+        seed
+    } else {
+        crate::println!("random: no entropy device, using the fixed seed");
+        FALLBACK_SEED
+    }
+}
+
+/// Fills the buffer with random bytes.
+pub fn fill(buf: &mut [u8]) {
+    RNG.lock().fill(buf);
+}
+
+struct ChaCha20 {
+    key: [u32; 8],
+    counter: u64,
+}
+impl ChaCha20 {
+    const fn new(seed: [u8; SEED_SIZE]) -> Self {
+        let mut key = [0; 8];
+        let mut i = 0;
+        while i < key.len() {
+            key[i] = u32::from_le_bytes([
+                seed[i * 4],
+                seed[i * 4 + 1],
+                seed[i * 4 + 2],
+                seed[i * 4 + 3],
+            ]);
+            i += 1;
+        }
+        ChaCha20 { key, counter: 0 }
+    }
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(BLOCK_SIZE) {
+            let block = self.next_block();
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+        let block = self.next_block();
+        *self = ChaCha20::new(block[..SEED_SIZE].try_into().unwrap());
+    }
+    fn next_block(&mut self) -> [u8; BLOCK_SIZE] {
+        let mut state = [0; 16];
+        state[..4].copy_from_slice(&CONSTANTS);
+        state[4..12].copy_from_slice(&self.key);
+        state[12] = self.counter as u32;
+        state[13] = (self.counter >> 32) as u32;
+        self.counter += 1;
+        let mut working = state;
+        for _ in 0..10 {
+            quarter_round(&mut working, 0, 4, 8, 12);
+            quarter_round(&mut working, 1, 5, 9, 13);
+            quarter_round(&mut working, 2, 6, 10, 14);
+            quarter_round(&mut working, 3, 7, 11, 15);
+            quarter_round(&mut working, 0, 5, 10, 15);
+            quarter_round(&mut working, 1, 6, 11, 12);
+            quarter_round(&mut working, 2, 7, 8, 13);
+            quarter_round(&mut working, 3, 4, 9, 14);
+        }
+        let mut block = [0; BLOCK_SIZE];
+        for (i, word) in working.iter().enumerate() {
+            let word = word.wrapping_add(state[i]);
+            block[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        block
+    }
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
@@ -0,0 +1,48 @@
+//! reaper -- Kernel thread cleaning up exited user progs.
+//!
+//! Exiting only marks a user prog exited. The reaper waits for its pending block requests, closes
+//! its files and shared memory regions and reloads its program image outside of the trap path.
+
+use crate::hardware::sync::Once;
+use crate::kthread::{self, KThread};
+use crate::scheduler::{self, Reason};
+use crate::{ipc, shm};
+
+static REAPER: Once<KThread> = Once::new();
+
+pub fn init() {
+    REAPER.call_once(|| kthread::spawn("reaper", run));
+}
+
+/// Wakes the reaper after a user prog exited.
+pub fn wake() {
+    if let Some(reaper) = REAPER.get() {
+        reaper.unpark();
+    }
+}
+
+fn run() {
+    loop {
+        while let Some(prog) = scheduler::find_exited() {
+            if prog.is_running() {
+                // Another hart still has to switch away from one of its threads.
+                kthread::yield_now();
+                continue;
+            }
+            if prog.has_blocked_thread(|reason| matches!(reason, Reason::Block(_))) {
+                // The device still writes to its memory. The request is released when it
+                // finishes, see `block::wake`.
+                kthread::yield_now();
+                continue;
+            }
+            prog.close_all_fds();
+            shm::close_all(prog);
+            let prog_info = prog.prog_info();
+            scheduler::end_prog(prog);
+            // New senders fail to find it until it is reloaded.
+            ipc::cancel(prog_info.id);
+            scheduler::init_prog(prog_info);
+        }
+        kthread::park();
+    }
+}
@@ -1,135 +1,467 @@
 //! The scheduler. Responsible for managing user programs.
+//!
+//! Every hart runs one task, a thread of a user prog or a kernel thread, at a time and picks the
+//! next rdy one that is not running on another hart. A hart without a task idles.
+//! The threads of a user prog share its memory, pmp region, files and shared memory regions.
 
 use crate::{
-    hardware::sync::Protected,
-    hardware::{clint, pmp},
+    elf,
+    fd::{Descriptor, FdTable},
+    hardware::{clint, hart, ipi, platform, pmp},
+    hardware::{stack::Stack, sync::RwLock},
+    kthread::{self, KThread},
     user_prog,
 };
 use riscv_utils::*;
 
-static PROG_LIST: Protected<ProgList> = Protected::new(ProgList::new());
+static PROG_LIST: RwLock<ProgList> = RwLock::new(ProgList::new());
+
+/// Maximum number of threads of a user prog, including the one it starts with.
+pub const MAX_USER_THREADS: usize = 4;
 
-pub fn boot_prog(prog: Prog) {
-    PROG_LIST.lock().boot_prog(prog);
-}
 pub fn end_prog(prog: Prog) {
-    let mut prog_list = PROG_LIST.lock();
+    let mut prog_list = PROG_LIST.write();
     prog_list.get(prog); // Check if the prog has the correct index.
     prog_list.progs[prog.idx] = None;
 }
+/// Loads the program image and adds the user prog. It is booted on the first switch to it.
 pub fn init_prog(prog_info: user_prog::Info) -> Prog {
-    let mut prog_list = PROG_LIST.lock();
+    let image = prog_info.load();
+    let mut prog_list = PROG_LIST.write();
     let idx = prog_list.get_free_idx();
-    prog_list.progs[idx] = Some(ProgData::new(prog_info));
+    prog_list.progs[idx] = Some(ProgData::new(prog_info, image));
     Prog {
         idx,
         id: prog_info.id,
+        thread: 0,
     }
 }
-/// Returns the current user prog.
+/// Returns the current user prog of the hart.
 pub fn cur() -> Prog {
-    let prog_list = PROG_LIST.lock();
-    if let Some(cur) = &prog_list.progs[prog_list.cur_prog_idx] {
-        return Prog {
-            idx: prog_list.cur_prog_idx,
-            id: cur.info.id,
-        };
+    let prog_list = PROG_LIST.read();
+    if let Some(Task::Prog(idx, thread)) = prog_list.cur[hart::id()] {
+        if let Some(cur) = &prog_list.progs[idx] {
+            return Prog {
+                idx,
+                id: cur.info.id,
+                thread,
+            };
+        }
     }
     panic!("Tried to access current user prog. But none was running");
 }
-/// Returns the next rdy or starting user prog after round robin.
-pub fn next() -> Option<Prog> {
-    let prog_list = PROG_LIST.lock();
-    let start = prog_list.cur_prog_idx + 1;
-    let prog_list_len = prog_list.progs.len();
-    for i in 0..prog_list_len {
-        let idx = (start + i) % prog_list_len;
-        if let Some(next) = &prog_list.progs[idx] {
-            if next.state == State::Rdy || next.state == State::Starting {
-                return Some(Prog {
-                    idx,
-                    id: next.info.id,
-                });
+/// Returns true if a user prog or kernel thread runs on the hart.
+pub fn is_running() -> bool {
+    PROG_LIST.read().cur[hart::id()].is_some()
+}
+/// Returns the user prog with the id if it exists. The handle refers to its first thread.
+pub fn find(id: user_prog::Id) -> Option<Prog> {
+    let prog_list = PROG_LIST.read();
+    for (idx, prog) in prog_list.progs.iter().enumerate() {
+        if let Some(prog) = prog {
+            if prog.info.id == id {
+                let thread = prog.threads.iter().position(Option::is_some).unwrap_or(0);
+                return Some(Prog { idx, id, thread });
             }
         }
     }
     None
 }
-/// Switches the current program.
+/// Returns the first exited user prog.
+pub fn find_exited() -> Option<Prog> {
+    let prog_list = PROG_LIST.read();
+    prog_list
+        .progs
+        .iter()
+        .enumerate()
+        .find_map(|(idx, prog)| match prog {
+            Some(prog) if prog.exited => Some(Prog {
+                idx,
+                id: prog.info.id,
+                thread: 0,
+            }),
+            _ => None,
+        })
+}
+/// Returns the first thread of a user prog blocked for the reason.
+pub fn find_blocked(reason: Reason) -> Option<Prog> {
+    find_blocked_by(|blocked| blocked == reason).map(|(prog, _)| prog)
+}
+/// Returns the first thread of a user prog blocked for a reason matching the predicate.
+pub fn find_blocked_by(predicate: impl Fn(Reason) -> bool) -> Option<(Prog, Reason)> {
+    let prog_list = PROG_LIST.read();
+    for (idx, prog) in prog_list.progs.iter().enumerate() {
+        if let Some(prog_data) = prog {
+            if let Some((prog, reason)) = prog_list.find_blocked_thread(idx, prog_data, &predicate)
+            {
+                return Some((prog, reason));
+            }
+        }
+    }
+    None
+}
+/// Returns the smallest key of the blocked threads of the user progs. Reasons without a key are
+/// skipped.
+pub fn min_blocked_by(key: impl Fn(Reason) -> Option<u64>) -> Option<u64> {
+    let prog_list = PROG_LIST.read();
+    prog_list
+        .progs
+        .iter()
+        .flatten()
+        .flat_map(|prog| prog.threads.iter().flatten())
+        .filter_map(|thread| match thread.state {
+            State::Blocked(reason) => key(reason),
+            _ => None,
+        })
+        .min()
+}
+/// Wakes all blocked threads of the user progs whose timeout expired before `now`.
+/// The system call of a woken thread returns [SysCallError::TimedOut].
+pub fn wake_expired(now: u64) {
+    let mut prog_list = PROG_LIST.write();
+    for idx in 0..prog_list.progs.len() {
+        for thread in 0..MAX_USER_THREADS {
+            if let Some(prog) = &prog_list.progs[idx] {
+                let expired = prog.threads[thread]
+                    .is_some_and(|data| data.timeout.is_some_and(|timeout| timeout <= now));
+                if expired {
+                    let prog = Prog {
+                        idx,
+                        id: prog.info.id,
+                        thread,
+                    };
+                    prog_list.set_rdy_with_ret(prog, to_ret(Err(SysCallError::TimedOut)));
+                }
+            }
+        }
+    }
+}
+/// Switches to the next rdy or starting thread of a user prog or rdy kernel thread after round
+/// robin. The hart idles if there is none. Does not return if a starting user prog is booted.
+pub fn schedule() {
+    PROG_LIST.write().schedule();
+}
+/// Switches to the thread of the user prog. Does nothing if the thread runs on another hart.
 pub fn switch(prog: Prog) {
-    PROG_LIST.lock().switch(prog);
+    PROG_LIST.write().switch(prog);
+}
+/// Reloads the pmp of the current user prog of the hart.
+/// Called on the harts running a user prog whose shared memory regions changed on another hart.
+fn reload_pmp() {
+    let prog_list = PROG_LIST.read();
+    if let Some(Task::Prog(idx, thread)) = prog_list.cur[hart::id()] {
+        let prog = Prog {
+            idx,
+            id: prog_list.get_by_idx(idx).info.id,
+            thread,
+        };
+        prog_list.update_pmp(prog);
+    }
+}
+/// Makes the harts reload their pmp. The caller must not hold the lock of the prog list.
+fn reload_remote_pmp(remote: [bool; hart::MAX_HARTS]) {
+    for hart in (0..hart::MAX_HARTS).filter(|&hart| remote[hart]) {
+        ipi::call(hart, reload_pmp);
+    }
+}
+/// Adds a kernel thread which starts at `mepc` with `arg` in `a0`.
+pub fn add_kernel_thread(mepc: usize, arg: usize) -> KThread {
+    let mut prog_list = PROG_LIST.write();
+    let idx = prog_list
+        .kthreads
+        .iter()
+        .position(|thread| thread.is_none())
+        .expect("No free index for kernel thread available");
+    prog_list.kthreads[idx] = Some(KThreadData {
+        mepc,
+        sp: kthread::init_stack(idx, arg),
+        state: KThreadState::Rdy,
+        unparked: false,
+    });
+    prog_list.wake_idle_hart();
+    KThread::new(idx)
+}
+/// Returns the current kernel thread of the hart.
+pub fn cur_kernel_thread() -> KThread {
+    match PROG_LIST.read().cur[hart::id()] {
+        Some(Task::Thread(idx)) => KThread::new(idx),
+        _ => panic!("Tried to access current kernel thread. But none was running"),
+    }
+}
+/// Parks the kernel thread unless it was unparked since it was last parked.
+/// It has to yield afterwards.
+pub fn park_kernel_thread(thread: KThread) {
+    let mut prog_list = PROG_LIST.write();
+    let thread = prog_list.get_kthread(thread);
+    if !core::mem::take(&mut thread.unparked) {
+        thread.state = KThreadState::Parked;
+    }
+}
+/// Makes the parked kernel thread rdy or lets its next park return immediately.
+pub fn unpark_kernel_thread(thread: KThread) {
+    let mut prog_list = PROG_LIST.write();
+    let thread = prog_list.get_kthread(thread);
+    match thread.state {
+        KThreadState::Parked => thread.state = KThreadState::Rdy,
+        _ => thread.unparked = true,
+    }
+    prog_list.wake_idle_hart();
+}
+/// Removes the kernel thread when the hart switches away from it. It has to yield afterwards.
+pub fn exit_kernel_thread(thread: KThread) {
+    PROG_LIST.write().get_kthread(thread).state = KThreadState::Exited;
 }
-/// Safes the user prog.
+/// Continues the current kernel thread after its `ecall` once it is scheduled again.
+pub fn kernel_thread_yield() {
+    let mut prog_list = PROG_LIST.write();
+    let thread = prog_list.cur_kthread_data();
+    thread.mepc += 4;
+    prog_list.schedule();
+}
+/// Safes the thread of the user prog or kernel thread.
 pub fn save_cur_prog(mepc: usize, sp: usize) {
     unsafe {
+        let mut prog_list = PROG_LIST.write();
+        if let Some(Task::Thread(_)) = prog_list.cur[hart::id()] {
+            let thread = prog_list.cur_kthread_data();
+            thread.mepc = mepc;
+            thread.sp = sp;
+            return;
+        }
         if mepc < 0x80100000usize {
             let mcause: usize;
             read_machine_reg!("mcause" => mcause);
-
+            prog_list.unlock();
             panic!("Interrupt in exception, mepc: {}, mcause: {}", mepc, mcause);
         }
-        let mut prog_list = PROG_LIST.lock();
-        let prog = prog_list.cur_prog_data();
-        prog.mepc = mepc;
-        prog.sp = sp;
+        let thread = prog_list.cur_thread_data();
+        thread.mepc = mepc;
+        thread.sp = sp;
     }
 }
 /// Returns the stack pointer for restoring.
+/// `mret` returns to user mode for user progs and to machine mode for kernel threads.
 pub fn restore_cur_prog() -> usize {
     unsafe {
-        let mut prog_list = PROG_LIST.lock();
-        let prog = prog_list.cur_prog_data();
-        if prog.state == State::Rdy {
-            write_machine_reg!(prog.mepc => "mepc");
-            return prog.sp;
+        let mut prog_list = PROG_LIST.write();
+        if let Some(Task::Thread(_)) = prog_list.cur[hart::id()] {
+            let thread = prog_list.cur_kthread_data();
+            write_machine_reg!(thread.mepc => "mepc");
+            set_previous_mode(true);
+            return thread.sp;
+        }
+        let thread = prog_list.cur_thread_data();
+        if thread.state == State::Rdy {
+            write_machine_reg!(thread.mepc => "mepc");
+            set_previous_mode(false);
+            return thread.sp;
         }
+        let state = thread.state;
         panic!(
             "Tried to restore user prog: {:?}, with state: {:?}",
-            prog.info.id, prog.state
+            prog_list.cur_prog_data().info.id,
+            state
         );
     }
 }
+/// Sets the privilege mode `mret` returns to. Kernel threads run with interrupts enabled.
+unsafe fn set_previous_mode(kernel: bool) {
+    const MSTATUS_MPP_M: usize = 0b11 << 11;
+    const MSTATUS_MPIE: usize = 1 << 7;
+    match kernel {
+        true => core::arch::asm!("csrs mstatus, {}", in(reg) MSTATUS_MPP_M | MSTATUS_MPIE),
+        false => core::arch::asm!("csrc mstatus, {}", in(reg) MSTATUS_MPP_M),
+    }
+}
+/// A thread of a user prog or a kernel thread with its indices.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Task {
+    Prog(usize, usize),
+    Thread(usize),
+}
 struct ProgList {
-    cur_prog_idx: usize,
+    /// Task running on each hart. [None] while the hart idles.
+    cur: [Option<Task>; hart::MAX_HARTS],
     progs: [Option<ProgData>; 2],
+    kthreads: [Option<KThreadData>; kthread::MAX_THREADS],
 }
 impl ProgList {
     const fn new() -> Self {
         ProgList {
-            cur_prog_idx: 0,
+            cur: [None; hart::MAX_HARTS],
             progs: [const { None }; 2],
+            kthreads: [None; kthread::MAX_THREADS],
+        }
+    }
+    /// Returns the task at the position of the round robin, the threads of the user progs come
+    /// first.
+    fn task(&self, pos: usize) -> Task {
+        match pos.checked_sub(self.progs.len() * MAX_USER_THREADS) {
+            Some(idx) => Task::Thread(idx),
+            None => Task::Prog(pos / MAX_USER_THREADS, pos % MAX_USER_THREADS),
         }
     }
-    /// Switches the current program.
+    fn position(&self, task: Task) -> usize {
+        match task {
+            Task::Prog(idx, thread) => idx * MAX_USER_THREADS + thread,
+            Task::Thread(idx) => self.progs.len() * MAX_USER_THREADS + idx,
+        }
+    }
+    fn schedule(&mut self) {
+        let hart = hart::id();
+        // Remove an exited kernel thread once the hart switches away from it.
+        if let Some(Task::Thread(idx)) = self.cur[hart] {
+            if self.kthreads[idx].is_some_and(|thread| thread.state == KThreadState::Exited) {
+                self.kthreads[idx] = None;
+            }
+        }
+        let start = self.cur[hart].map_or(0, |task| self.position(task) + 1);
+        let len = self.progs.len() * MAX_USER_THREADS + self.kthreads.len();
+        for i in 0..len {
+            let task = self.task((start + i) % len);
+            if self.runs_on_other_hart(task) {
+                continue;
+            }
+            match task {
+                Task::Prog(idx, thread) => {
+                    let Some(next) = &self.progs[idx] else {
+                        continue;
+                    };
+                    let runnable = next.threads[thread].is_some_and(|thread| {
+                        thread.state == State::Rdy || thread.state == State::Starting
+                    });
+                    if runnable && !next.exited {
+                        let prog = Prog {
+                            idx,
+                            id: next.info.id,
+                            thread,
+                        };
+                        self.switch(prog);
+                        return;
+                    }
+                }
+                Task::Thread(idx) => {
+                    if self.kthreads[idx].is_some_and(|thread| thread.state == KThreadState::Rdy) {
+                        self.cur[hart] = Some(task);
+                        return;
+                    }
+                }
+            }
+        }
+        self.cur[hart] = None;
+    }
+    /// Switches to the thread of the user prog.
     fn switch(&mut self, prog: Prog) {
+        if self.runs_on_other_hart(Task::Prog(prog.idx, prog.thread)) {
+            return;
+        }
         let prog_data = self.get(prog);
-        match prog_data.state {
+        match self.get_thread(prog).state {
             State::Rdy => {
-                pmp::switch_prog_pmp(prog_data.info.pmp_idx);
-                self.cur_prog_idx = prog.idx;
+                pmp::switch_prog_pmp(prog_data.info.pmp_idx, &prog_data.shm);
+                self.cur[hart::id()] = Some(Task::Prog(prog.idx, prog.thread));
             }
             State::Starting => {
                 self.boot_prog(prog);
             }
-            State::Blocked(_) => {
+            state @ (State::Blocked(_) | State::Exited(_)) => {
                 panic!(
-                    "Tried to switch to user prog: {:?}, with state: {:?}",
-                    prog_data.info.id, prog_data.state
+                    "Tried to switch to thread: {} of user prog: {:?}, with state: {:?}",
+                    prog.thread, prog_data.info.id, state
                 )
             }
         }
     }
     fn boot_prog(&mut self, prog: Prog) {
         unsafe {
-            let prog_data = self.get_mut(prog);
-            prog_data.state = State::Rdy;
-            riscv_utils::write_machine_reg!(prog_data.info.boot_mepc => "mepc");
-            crate::println!("\n\n## Starting {:?} ##", prog_data.info.id);
+            let thread = self.get_thread_mut(prog);
+            thread.state = State::Rdy;
+            riscv_utils::write_machine_reg!(thread.mepc => "mepc");
+            // The TLS block of the first thread is at the end of the memory, see elf::load.
+            let prog_data = self.get(prog);
+            let tls = prog_data.tls;
+            let tp = tls
+                .block_below(prog_data.info.mem_end)
+                .expect("The loader left no room for the TLS block");
+            tls.init_block(tp);
+            crate::println!("\n\n## Starting {:?} ##", prog.id);
             self.switch(prog);
             clint::set_time_cmp();
-            PROG_LIST.unsafe_unlock();
-            core::arch::asm!("mret");
+            set_previous_mode(false);
+            PROG_LIST.unsafe_unlock_write();
+            // Leave the trap handling of the hart like `trap_return` in `exception.S`.
+            core::arch::asm!(
+                "csrr {0}, mscratch",
+                "sd zero, 0({0})",
+                "mv tp, {1}",
+                "mret",
+                out(reg) _,
+                in(reg) tp,
+            );
+        }
+    }
+    fn runs_on_other_hart(&self, task: Task) -> bool {
+        let hart = hart::id();
+        self.cur
+            .iter()
+            .enumerate()
+            .any(|(other, cur)| other != hart && *cur == Some(task))
+    }
+    /// Returns the harts running a thread of the user prog at the index.
+    fn running_harts(&self, idx: usize) -> [bool; hart::MAX_HARTS] {
+        self.cur
+            .map(|cur| matches!(cur, Some(Task::Prog(running, _)) if running == idx))
+    }
+    /// Sends a reschedule request to an idle hart, e.g. after a user prog got rdy.
+    fn wake_idle_hart(&self) {
+        let hart = hart::id();
+        let harts = platform::get().harts.min(hart::MAX_HARTS);
+        if let Some(idle) = (0..harts).find(|&other| other != hart && self.cur[other].is_none()) {
+            ipi::reschedule(idle);
+        }
+    }
+    /// Updates the pmp if a thread of the user prog is the current one of the hart.
+    /// Returns the other harts running threads of the user prog, they have to reload their pmp.
+    fn update_pmp(&self, prog: Prog) -> [bool; hart::MAX_HARTS] {
+        let mut remote = self.running_harts(prog.idx);
+        if core::mem::take(&mut remote[hart::id()]) {
+            let prog_data = self.get(prog);
+            pmp::switch_prog_pmp(prog_data.info.pmp_idx, &prog_data.shm);
+        }
+        remote
+    }
+    fn set_rdy_with_ret(&mut self, prog: Prog, ret: usize) {
+        let thread = self.get_thread_mut(prog);
+        unsafe {
+            let mut stack = Stack::new(thread.sp);
+            stack.set_ret(ret);
+            stack.write();
         }
+        thread.state = State::Rdy;
+        thread.timeout = None;
+        self.wake_idle_hart();
+    }
+    /// Returns the first thread of the user prog at the index blocked for a reason matching the
+    /// predicate.
+    fn find_blocked_thread(
+        &self,
+        idx: usize,
+        prog: &ProgData,
+        predicate: impl Fn(Reason) -> bool,
+    ) -> Option<(Prog, Reason)> {
+        prog.threads.iter().enumerate().find_map(|(thread, data)| {
+            match data.map(|data| data.state) {
+                Some(State::Blocked(reason)) if predicate(reason) => {
+                    let prog = Prog {
+                        idx,
+                        id: prog.info.id,
+                        thread,
+                    };
+                    Some((prog, reason))
+                }
+                _ => None,
+            }
+        })
     }
     fn get_free_idx(&self) -> usize {
         for (idx, prog) in self.progs.iter().enumerate() {
@@ -157,6 +489,11 @@ impl ProgList {
             prog.id, prog.idx
         );
     }
+    fn get_by_idx(&self, idx: usize) -> &ProgData {
+        self.progs[idx]
+            .as_ref()
+            .unwrap_or_else(|| panic!("Tried to access a not existing user prog at: {}", idx))
+    }
     /// Returns the ProgData to a Prog.
     ///
     /// Panics if the ProgData is not found or the option is [None].
@@ -175,67 +512,385 @@ impl ProgList {
             prog.id, prog.idx
         );
     }
+    /// Returns the ThreadData of the thread the Prog refers to.
+    ///
+    /// Panics if the user prog or the thread does not exist.
+    fn get_thread(&self, prog: Prog) -> &ThreadData {
+        self.get(prog).threads[prog.thread]
+            .as_ref()
+            .unwrap_or_else(|| {
+                panic!(
+                    "Tried to access a not existing thread: {}, of user prog: {:?}",
+                    prog.thread, prog.id
+                )
+            })
+    }
+    fn get_thread_mut(&mut self, prog: Prog) -> &mut ThreadData {
+        self.get_mut(prog).threads[prog.thread]
+            .as_mut()
+            .unwrap_or_else(|| {
+                panic!(
+                    "Tried to access a not existing thread: {}, of user prog: {:?}",
+                    prog.thread, prog.id
+                )
+            })
+    }
     fn cur_prog_data(&mut self) -> &mut ProgData {
-        if let Some(cur) = &mut self.progs[self.cur_prog_idx] {
-            return cur;
+        if let Some(Task::Prog(idx, _)) = self.cur[hart::id()] {
+            if let Some(cur) = &mut self.progs[idx] {
+                return cur;
+            }
         }
         panic!("Tried to access current user prog, but none was running");
     }
+    fn cur_thread_data(&mut self) -> &mut ThreadData {
+        if let Some(Task::Prog(idx, thread)) = self.cur[hart::id()] {
+            if let Some(Some(cur)) = self.progs[idx]
+                .as_mut()
+                .map(|prog| &mut prog.threads[thread])
+            {
+                return cur;
+            }
+        }
+        panic!("Tried to access current thread of a user prog, but none was running");
+    }
+    fn cur_kthread_data(&mut self) -> &mut KThreadData {
+        match self.cur[hart::id()] {
+            Some(Task::Thread(idx)) => self.get_kthread(KThread::new(idx)),
+            _ => panic!("Tried to access current kernel thread, but none was running"),
+        }
+    }
+    /// Panics if the kernel thread does not exist.
+    fn get_kthread(&mut self, thread: KThread) -> &mut KThreadData {
+        self.kthreads[thread.idx()]
+            .as_mut()
+            .unwrap_or_else(|| panic!("Tried to access a not existing kernel thread: {:?}", thread))
+    }
 }
 
-#[derive(PartialEq, Clone, Copy)]
+/// A handle to a thread of a user prog. Handles to threads of the same user prog are equal, as
+/// files, shared memory regions and the pid belong to the user prog.
+#[derive(Clone, Copy)]
 pub struct Prog {
     idx: usize,
     id: user_prog::Id,
+    thread: usize,
+}
+impl PartialEq for Prog {
+    fn eq(&self, other: &Self) -> bool {
+        self.idx == other.idx && self.id == other.id
+    }
 }
 impl Prog {
     pub fn set_rdy(&self) {
-        PROG_LIST.lock().get_mut(*self).state = State::Rdy;
+        let mut prog_list = PROG_LIST.write();
+        let thread = prog_list.get_thread_mut(*self);
+        thread.state = State::Rdy;
+        thread.timeout = None;
+        prog_list.wake_idle_hart();
+    }
+    /// Sets the thread rdy and its system call returns `ret`.
+    pub fn set_rdy_with_ret(&self, ret: usize) {
+        PROG_LIST.write().set_rdy_with_ret(*self, ret);
     }
     pub fn is_blocked(&self, reason: Reason) -> bool {
-        PROG_LIST.lock().get(*self).state == State::Blocked(reason)
+        PROG_LIST.read().get_thread(*self).state == State::Blocked(reason)
+    }
+    /// Returns the first thread of the user prog blocked for the reason.
+    pub fn find_blocked_thread(&self, reason: Reason) -> Option<Prog> {
+        let prog_list = PROG_LIST.read();
+        prog_list
+            .find_blocked_thread(self.idx, prog_list.get(*self), |blocked| blocked == reason)
+            .map(|(prog, _)| prog)
+    }
+    /// Returns true if a thread of the user prog is blocked for a reason matching the predicate.
+    pub fn has_blocked_thread(&self, predicate: impl Fn(Reason) -> bool) -> bool {
+        let prog_list = PROG_LIST.read();
+        prog_list
+            .find_blocked_thread(self.idx, prog_list.get(*self), predicate)
+            .is_some()
+    }
+    /// Marks the user prog exited. Its threads stop running and it is cleaned up by the reaper.
+    pub fn set_exited(&self) {
+        let mut prog_list = PROG_LIST.write();
+        prog_list.get_mut(*self).exited = true;
+        let hart = hart::id();
+        for (other, running) in prog_list.running_harts(self.idx).into_iter().enumerate() {
+            if running && other != hart {
+                ipi::reschedule(other);
+            }
+        }
     }
     pub fn set_blocked(&self, reason: Reason) {
-        PROG_LIST.lock().get_mut(*self).state = State::Blocked(reason);
+        PROG_LIST.write().get_thread_mut(*self).state = State::Blocked(reason);
+    }
+    /// Blocks the thread until it is woken or the timer reaches the timeout.
+    pub fn set_blocked_until(&self, reason: Reason, timeout: u64) {
+        let mut prog_list = PROG_LIST.write();
+        let thread = prog_list.get_thread_mut(*self);
+        thread.state = State::Blocked(reason);
+        thread.timeout = Some(timeout);
+        clint::set_time_cmp_before(timeout);
     }
     pub fn increment_mepc(&self) {
-        PROG_LIST.lock().get_mut(*self).mepc += 4;
+        PROG_LIST.write().get_thread_mut(*self).mepc += 4;
+    }
+    /// Adds a rdy thread to the user prog which starts at `mepc` with the registers stored at
+    /// `sp`. Returns the new thread.
+    pub fn add_thread(&self, mepc: usize, sp: usize) -> Result<Prog, SysCallError> {
+        let mut prog_list = PROG_LIST.write();
+        let threads = &mut prog_list.get_mut(*self).threads;
+        let thread = threads
+            .iter()
+            .position(|thread| thread.is_none())
+            .ok_or(SysCallError::NoSpace)?;
+        threads[thread] = Some(ThreadData {
+            mepc,
+            sp,
+            state: State::Rdy,
+            timeout: None,
+        });
+        prog_list.wake_idle_hart();
+        Ok(Prog { thread, ..*self })
+    }
+    /// Marks the thread exited with the code. A joining thread is woken and returns the code.
+    /// The thread has to yield afterwards.
+    ///
+    /// Returns true if it was the last running thread, the user prog is exited then.
+    pub fn exit_thread(&self, code: usize) -> bool {
+        let mut prog_list = PROG_LIST.write();
+        prog_list.get_thread_mut(*self).state = State::Exited(code);
+        let joiner = prog_list
+            .find_blocked_thread(self.idx, prog_list.get(*self), |reason| {
+                reason == Reason::Join(self.thread)
+            })
+            .map(|(joiner, _)| joiner);
+        if let Some(joiner) = joiner {
+            prog_list.get_mut(*self).threads[self.thread] = None;
+            prog_list.set_rdy_with_ret(joiner, code);
+        }
+        let prog_data = prog_list.get_mut(*self);
+        let last = prog_data
+            .threads
+            .iter()
+            .flatten()
+            .all(|thread| matches!(thread.state, State::Exited(_)));
+        prog_data.exited |= last;
+        last
+    }
+    /// Joins the thread of the user prog with the id.
+    /// Returns its exit code if it already exited, otherwise the joining thread is blocked.
+    pub fn join_thread(&self, thread: usize) -> Result<Option<usize>, SysCallError> {
+        let mut prog_list = PROG_LIST.write();
+        let joined = Prog { thread, ..*self };
+        let prog_data = prog_list.get(*self);
+        let state = match prog_data.threads.get(thread) {
+            Some(Some(data)) if thread != self.thread => data.state,
+            _ => return Err(SysCallError::InvalidArgument),
+        };
+        let joining = prog_list
+            .find_blocked_thread(self.idx, prog_data, |reason| reason == Reason::Join(thread));
+        if joining.is_some() {
+            return Err(SysCallError::InvalidArgument);
+        }
+        if let State::Exited(code) = state {
+            prog_list.get_mut(joined).threads[thread] = None;
+            return Ok(Some(code));
+        }
+        prog_list.get_thread_mut(*self).state = State::Blocked(Reason::Join(thread));
+        Ok(None)
+    }
+    /// Returns true if a thread of the user prog runs on a hart.
+    pub fn is_running(&self) -> bool {
+        PROG_LIST.read().running_harts(self.idx).contains(&true)
     }
     pub fn id(&self) -> user_prog::Id {
-        PROG_LIST.lock().get(*self).info.id
+        PROG_LIST.read().get(*self).info.id
+    }
+    /// Returns the id of the thread within its user prog.
+    pub fn thread(&self) -> usize {
+        self.thread
     }
     pub fn prog_info(&self) -> user_prog::Info {
-        PROG_LIST.lock().get(*self).info
+        PROG_LIST.read().get(*self).info
+    }
+    /// Returns the thread-local storage template of the program image.
+    pub fn tls(&self) -> elf::Tls {
+        PROG_LIST.read().get(*self).tls
     }
     pub fn sp(&self) -> usize {
-        PROG_LIST.lock().get(*self).sp
+        PROG_LIST.read().get_thread(*self).sp
+    }
+    pub fn fd(&self, fd: usize) -> Result<Descriptor, SysCallError> {
+        PROG_LIST.read().get(*self).fds.get(fd)
+    }
+    pub fn open_fd(&self, descriptor: Descriptor) -> Result<usize, SysCallError> {
+        PROG_LIST.write().get_mut(*self).fds.open(descriptor, *self)
+    }
+    pub fn set_fd(&self, fd: usize, descriptor: Descriptor) -> Result<(), SysCallError> {
+        PROG_LIST.write().get_mut(*self).fds.set(fd, descriptor)
+    }
+    pub fn close_fd(&self, fd: usize) -> Result<(), SysCallError> {
+        PROG_LIST.write().get_mut(*self).fds.close(fd, *self)
+    }
+    pub fn dup2_fd(&self, old_fd: usize, new_fd: usize) -> Result<usize, SysCallError> {
+        PROG_LIST
+            .write()
+            .get_mut(*self)
+            .fds
+            .dup2(old_fd, new_fd, *self)
+    }
+    pub fn close_all_fds(&self) {
+        PROG_LIST.write().get_mut(*self).fds.close_all(*self);
+    }
+    /// Returns true if the memory at `addr` lies in the memory of the user prog or in one of its
+    /// shared memory regions.
+    pub fn can_access(&self, addr: usize, len: usize) -> bool {
+        let Some(end) = addr.checked_add(len) else {
+            return false;
+        };
+        let prog_list = PROG_LIST.read();
+        let prog_data = prog_list.get(*self);
+        let inside = |start: usize, size: usize| addr >= start && end - start <= size;
+        let info = prog_data.info;
+        inside(info.mem_start, info.mem_end - info.mem_start)
+            || prog_data
+                .shm
+                .iter()
+                .flatten()
+                .any(|&shm| inside(shm, SHM_SIZE))
+    }
+    pub fn has_shm(&self, addr: usize) -> bool {
+        PROG_LIST.read().get(*self).shm.contains(&Some(addr))
+    }
+    /// Grants access to the shared memory region at the address.
+    pub fn map_shm(&self, addr: usize) -> Result<(), SysCallError> {
+        let mut prog_list = PROG_LIST.write();
+        let shm = &mut prog_list.get_mut(*self).shm;
+        let slot = shm
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(SysCallError::NoSpace)?;
+        *slot = Some(addr);
+        let remote = prog_list.update_pmp(*self);
+        prog_list.unlock();
+        reload_remote_pmp(remote);
+        Ok(())
+    }
+    /// Revokes access to the shared memory region at the address.
+    pub fn unmap_shm(&self, addr: usize) -> Result<(), SysCallError> {
+        let mut prog_list = PROG_LIST.write();
+        let shm = &mut prog_list.get_mut(*self).shm;
+        let slot = shm
+            .iter_mut()
+            .find(|slot| **slot == Some(addr))
+            .ok_or(SysCallError::InvalidArgument)?;
+        *slot = None;
+        let remote = prog_list.update_pmp(*self);
+        prog_list.unlock();
+        reload_remote_pmp(remote);
+        Ok(())
+    }
+    /// Revokes access to all shared memory regions. Returns their addresses.
+    pub fn unmap_all_shm(&self) -> [Option<usize>; pmp::SHM_ENTRIES] {
+        let mut prog_list = PROG_LIST.write();
+        let shm = core::mem::take(&mut prog_list.get_mut(*self).shm);
+        let remote = prog_list.update_pmp(*self);
+        prog_list.unlock();
+        reload_remote_pmp(remote);
+        shm
     }
 }
 #[derive(PartialEq)]
 struct ProgData {
     info: user_prog::Info,
-    mepc: usize,
-    sp: usize,
-    state: State,
+    /// The threads share the memory, files and shared memory regions of the user prog.
+    threads: [Option<ThreadData>; MAX_USER_THREADS],
+    /// Template of the TLS blocks of the threads.
+    tls: elf::Tls,
+    /// Set once the user prog exited. Its threads are not scheduled anymore and it waits for the
+    /// reaper to clean it up.
+    exited: bool,
+    fds: FdTable,
+    /// Addresses of the shared memory regions the user prog can access.
+    shm: [Option<usize>; pmp::SHM_ENTRIES],
 }
 impl ProgData {
-    fn new(prog_info: user_prog::Info) -> Self {
-        ProgData {
-            info: prog_info,
+    fn new(prog_info: user_prog::Info, image: elf::Image) -> Self {
+        let mut threads = [None; MAX_USER_THREADS];
+        threads[0] = Some(ThreadData {
+            mepc: image.entry,
             sp: 0,
-            mepc: 0,
             state: State::Starting,
+            timeout: None,
+        });
+        ProgData {
+            info: prog_info,
+            threads,
+            tls: image.tls,
+            exited: false,
+            fds: FdTable::new(),
+            shm: [None; pmp::SHM_ENTRIES],
         }
     }
 }
 
+/// The registers of a thread of a user prog are stored on its stack.
+#[derive(Clone, Copy, PartialEq)]
+struct ThreadData {
+    mepc: usize,
+    sp: usize,
+    state: State,
+    /// Timer value at which a blocked thread is woken.
+    timeout: Option<u64>,
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 enum State {
     Rdy,
     Blocked(Reason),
     Starting,
+    /// Exited with the code, waits to be joined.
+    Exited(usize),
+}
+
+#[derive(Clone, Copy)]
+struct KThreadData {
+    mepc: usize,
+    sp: usize,
+    state: KThreadState,
+    /// Set by an unpark while the kernel thread was not parked.
+    unparked: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum KThreadState {
+    Rdy,
+    Parked,
+    Exited,
 }
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Reason {
     Uart,
+    /// Waits for the user prog to receive the message.
+    Send(user_prog::Id),
+    /// Waits for the user prog to receive the message and reply to it.
+    Call(user_prog::Id),
+    /// Waits for a message from the user prog or from any user prog if [None].
+    Receive(Option<user_prog::Id>),
+    /// Waits for a futex wake on the address.
+    Futex(usize),
+    /// Waits for the semaphore with the handle to be posted.
+    Semaphore(usize),
+    /// Waits for any of the flags in the mask to be set on the event with the handle.
+    Event {
+        handle: usize,
+        mask: usize,
+    },
+    /// Waits for the block device request with the id to finish.
+    Block(usize),
+    /// Waits for the wall time in nanoseconds since the Unix epoch.
+    Alarm(u64),
+    /// Waits for the thread with the id of the same user prog to exit.
+    Join(usize),
 }
@@ -0,0 +1,79 @@
+//! semaphore -- Named counting semaphores.
+//!
+//! User progs waiting on a semaphore are blocked with [Reason::Semaphore].
+//! A post hands the unit directly to a waiting user prog.
+
+use riscv_utils::*;
+
+use crate::{
+    hardware::{clint, sync::Protected},
+    name::{self, Name, Named},
+    scheduler::{self, Reason},
+    sys_call::sys_yield,
+};
+
+const SEMAPHORES: usize = 8;
+
+static SEMAPHORE_LIST: Protected<[Option<Semaphore>; SEMAPHORES]> =
+    Protected::new([None; SEMAPHORES]);
+
+#[derive(Clone, Copy)]
+struct Semaphore {
+    name: Name,
+    count: usize,
+}
+impl Named for Semaphore {
+    fn name(&self) -> Name {
+        self.name
+    }
+}
+
+/// Returns the handle of the semaphore with the name.
+/// The semaphore is created with the count if it does not exist.
+pub fn create(name: &[u8], count: usize) -> Result<usize, SysCallError> {
+    let name = Name::new(name)?;
+    name::find_or_create(&mut *SEMAPHORE_LIST.lock(), name, |_| Semaphore {
+        name,
+        count,
+    })
+}
+
+/// Decrements the count of the semaphore or blocks the current user prog until it is posted.
+/// A `timeout` of 0 waits forever, otherwise it is measured in timer ticks.
+///
+/// Returns [None] if the user prog is blocked. The return value is written on wake.
+pub fn wait(handle: usize, timeout: usize) -> Option<usize> {
+    let cur = scheduler::cur();
+    cur.increment_mepc();
+    let mut semaphore_list = SEMAPHORE_LIST.lock();
+    let Some(Some(semaphore)) = semaphore_list.get_mut(handle) else {
+        return Some(to_ret(Err(SysCallError::InvalidArgument)));
+    };
+    if semaphore.count > 0 {
+        semaphore.count -= 1;
+        return Some(0);
+    }
+    // Blocked while holding the lock, so a post on another hart cannot miss the user prog.
+    if timeout == 0 {
+        cur.set_blocked(Reason::Semaphore(handle));
+    } else {
+        let timeout = clint::mtime().saturating_add(timeout as u64);
+        cur.set_blocked_until(Reason::Semaphore(handle), timeout);
+    }
+    semaphore_list.unlock();
+    sys_yield();
+    None
+}
+
+/// Wakes a user prog waiting on the semaphore or increments the count if none is waiting.
+pub fn post(handle: usize) -> Result<usize, SysCallError> {
+    let mut semaphore_list = SEMAPHORE_LIST.lock();
+    let Some(Some(semaphore)) = semaphore_list.get_mut(handle) else {
+        return Err(SysCallError::InvalidArgument);
+    };
+    match scheduler::find_blocked(Reason::Semaphore(handle)) {
+        Some(prog) => prog.set_rdy_with_ret(0),
+        None => semaphore.count += 1,
+    }
+    Ok(0)
+}
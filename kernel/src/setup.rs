@@ -1,24 +1,50 @@
 //! Global kernel setup.
 
-use crate::hardware::binary_struct::BinaryStruct;
-use crate::{asm, hardware};
+use crate::hardware::{binary_struct::BinaryStruct, platform};
+use crate::{asm, hardware, initramfs, shm, user_prog};
 use riscv_utils::*;
 
-/// Global kernel setup. It must only be called once.
-pub unsafe fn setup() {
+/// Global kernel setup on the boot hart. It must only be called once.
+/// The device tree at `fdt_addr` is passed by the boot loader.
+pub unsafe fn setup(fdt_addr: usize) {
+    // Discover the devices before initializing their drivers.
+    hardware::platform::init(fdt_addr);
+    check_memory_layout();
+    // Init hardware interrupt.
+    hardware::plic::init();
+    hardware::uart::init();
+    hardware::rtc::init();
+    hardware::virtio::init();
+    hardware::virtio_blk::init();
+    hardware::virtio_rng::init();
+    hardware::pci::init();
+    crate::vfs::init();
+    crate::reaper::init();
+    setup_hart();
+}
+
+/// Panics if a fixed region of the memory lies outside the memory of the machine.
+fn check_memory_layout() {
+    for info in [user_prog::USER1, user_prog::USER2] {
+        platform::check_memory(info.path, info.mem_start..info.mem_end);
+    }
+    platform::check_memory("shared memory", shm::MEMORY);
+    platform::check_memory("initramfs", initramfs::FIRST_HEADER);
+}
+
+/// Setup of the machine registers of the calling hart. Called once by every hart.
+pub unsafe fn setup_hart() {
     // Set previous privilege mode to user so mret returns to user mode.
+    // Machine-mode interrupts stay disabled, they are taken while user progs run.
     let mstatus: usize;
     read_machine_reg!("mstatus" => mstatus);
     let mut mstatus = BinaryStruct::from(mstatus);
     mstatus.write_register_entry(MSTATUS_MPP_U.0);
     mstatus.write_register_entry(MSTATUS_MPP_U.1);
-
-    // Enable machine-mode interrupts.
-    mstatus.write_register_entry(MSTATUS_MIE);
     write_machine_reg!(mstatus.into_inner() => "mstatus");
 
-    // Set the machine-mode trap handler.
-    let trap_handler = asm::exception as usize;
+    // Set the machine-mode trap table in vectored mode.
+    let trap_handler = asm::trap_vector as *const () as usize | MTVEC_VECTORED;
     // Disable paging for now.
     let paging = 0usize;
     write_machine_reg!(
@@ -27,9 +53,6 @@ pub unsafe fn setup() {
     );
     // Init timer interrupt.
     hardware::clint::init();
-    // Init hardware interrupt.
-    hardware::plic::init();
-    hardware::uart::init();
     // Configure physical memory protection.
     hardware::pmp::init();
     // Enable software interrupts (ecall) in M mode. Enable timer interrupts.
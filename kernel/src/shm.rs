@@ -0,0 +1,90 @@
+//! shm -- Shared memory regions between user progs.
+//!
+//! A region is identified by its name and granted to a user prog by an additional pmp entry.
+//! The region is freed when the last user prog closes it.
+
+use riscv_utils::{SysCallError, SHM_SIZE};
+
+use crate::{
+    hardware::sync::Protected,
+    name::{self, Name, Named},
+    scheduler::Prog,
+};
+
+/// Start of the memory reserved for shared memory regions.
+const BASE_ADDR: usize = 0x8030_0000;
+const REGIONS: usize = 8;
+/// The memory of all regions.
+pub const MEMORY: core::ops::Range<usize> = BASE_ADDR..BASE_ADDR + REGIONS * SHM_SIZE;
+
+static REGION_LIST: Protected<[Option<Region>; REGIONS]> = Protected::new([None; REGIONS]);
+
+#[derive(Clone, Copy)]
+struct Region {
+    name: Name,
+    /// Number of user progs the region is granted to.
+    users: usize,
+}
+impl Named for Region {
+    fn name(&self) -> Name {
+        self.name
+    }
+}
+
+/// Grants the region with the name to the user prog. The region is created if it does not exist.
+/// Returns the address of the region.
+pub fn open(prog: Prog, name: &[u8]) -> Result<usize, SysCallError> {
+    let name = Name::new(name)?;
+    let mut region_list = REGION_LIST.lock();
+    let idx = name::find_or_create(&mut *region_list, name, |idx| {
+        unsafe { (region_addr(idx) as *mut u8).write_bytes(0, SHM_SIZE) };
+        Region { name, users: 0 }
+    })?;
+    // The user prog counts as user while the lock is released, so the region is not freed.
+    region_list[idx]
+        .as_mut()
+        .expect("Region was just found")
+        .users += 1;
+    region_list.unlock();
+    let addr = region_addr(idx);
+    if prog.has_shm(addr) {
+        release(addr);
+        return Ok(addr);
+    }
+    // Mapping reloads the pmp of other harts, which must not wait for the lock meanwhile.
+    if let Err(err) = prog.map_shm(addr) {
+        release(addr);
+        return Err(err);
+    }
+    Ok(addr)
+}
+
+/// Revokes the region at the address from the user prog.
+pub fn close(prog: Prog, addr: usize) -> Result<(), SysCallError> {
+    prog.unmap_shm(addr)?;
+    release(addr);
+    Ok(())
+}
+
+/// Revokes all regions from the user prog, e.g. when it exits.
+pub fn close_all(prog: Prog) {
+    for addr in prog.unmap_all_shm().into_iter().flatten() {
+        release(addr);
+    }
+}
+
+/// Decrements the users of the region and frees it if no user is left.
+fn release(addr: usize) {
+    let mut region_list = REGION_LIST.lock();
+    let idx = (addr - BASE_ADDR) / SHM_SIZE;
+    if let Some(region) = &mut region_list[idx] {
+        region.users -= 1;
+        if region.users == 0 {
+            region_list[idx] = None;
+        }
+    }
+}
+
+fn region_addr(idx: usize) -> usize {
+    BASE_ADDR + idx * SHM_SIZE
+}
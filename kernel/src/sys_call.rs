@@ -2,30 +2,26 @@
 
 use riscv_utils::*;
 
-use super::hardware::{memory_mapping::MemoryMapping, uart};
-use crate::scheduler;
+use super::hardware::{rtc, test_finisher, uart};
+use crate::{
+    alarm, block, event,
+    fd::{Descriptor, Io},
+    futex, ipc, random, reaper, scheduler, semaphore, shm, thread, vfs,
+};
 
 fn sys_call_from(number: usize) -> SysCall {
     SysCall::try_from(number as isize)
         .unwrap_or_else(|_| panic!("Illegal syscall number: {}", number))
 }
 
-pub fn sys_call(number: usize, param_0: usize, param_1: usize) -> Option<usize> {
+pub fn sys_call(number: usize, param_0: usize, param_1: usize, param_2: usize) -> Option<usize> {
     match sys_call_from(number) {
-        SysCall::PrintString => {
-            unsafe { print_string(param_0, param_1) };
-            scheduler::cur().increment_mepc();
-            None
-        }
-        SysCall::PrintChar => {
-            uart::print_char(param_0 as u8 as char);
-            scheduler::cur().increment_mepc();
-            None
-        }
-        SysCall::GetChar => {
-            scheduler::cur().increment_mepc();
-            get_char()
-        }
+        SysCall::PrintString => match user_slice(param_0, param_1) {
+            Ok(string) => write(STDOUT, string).map(|_| 0),
+            Err(err) => fail(err),
+        },
+        SysCall::PrintChar => write(STDOUT, &[param_0 as u8]).map(|_| 0),
+        SysCall::GetChar => get_char(),
         SysCall::PrintNum => {
             uart::print_num(param_0);
             scheduler::cur().increment_mepc();
@@ -40,54 +36,248 @@ pub fn sys_call(number: usize, param_0: usize, param_1: usize) -> Option<usize>
             sys_yield();
             None
         }
-        SysCall::UartOpen => {
-            let open = uart::open(scheduler::cur());
+        SysCall::Read => match user_slice_mut(param_1, param_2) {
+            Ok(buf) => read(param_0, buf).map(to_ret),
+            Err(err) => fail(err),
+        },
+        SysCall::Write => match user_slice(param_1, param_2) {
+            Ok(buf) => write(param_0, buf).map(to_ret),
+            Err(err) => fail(err),
+        },
+        SysCall::Close => {
+            let close = scheduler::cur().close_fd(param_0);
+            scheduler::cur().increment_mepc();
+            Some(to_ret(close.map(|_| 0)))
+        }
+        SysCall::Dup2 => {
+            let dup = scheduler::cur().dup2_fd(param_0, param_1);
+            scheduler::cur().increment_mepc();
+            Some(to_ret(dup))
+        }
+        SysCall::Send => ipc::send(param_0, false),
+        SysCall::Receive => ipc::receive(),
+        SysCall::Call => ipc::send(param_0, true),
+        SysCall::ShmOpen => {
+            let open =
+                user_slice(param_0, param_1).and_then(|name| shm::open(scheduler::cur(), name));
+            scheduler::cur().increment_mepc();
+            Some(to_ret(open))
+        }
+        SysCall::ShmClose => {
+            let close = shm::close(scheduler::cur(), param_0);
+            scheduler::cur().increment_mepc();
+            Some(to_ret(close.map(|_| 0)))
+        }
+        SysCall::FutexWait => futex::wait(param_0, param_1, param_2),
+        SysCall::FutexWake => Some(futex::wake(param_0, param_1)),
+        SysCall::SemCreate => {
+            let create =
+                user_slice(param_0, param_1).and_then(|name| semaphore::create(name, param_2));
+            scheduler::cur().increment_mepc();
+            Some(to_ret(create))
+        }
+        SysCall::SemWait => semaphore::wait(param_0, param_1),
+        SysCall::SemPost => {
+            let post = semaphore::post(param_0);
+            scheduler::cur().increment_mepc();
+            Some(to_ret(post))
+        }
+        SysCall::EventCreate => {
+            let create = user_slice(param_0, param_1).and_then(event::create);
+            scheduler::cur().increment_mepc();
+            Some(to_ret(create))
+        }
+        SysCall::EventWait => event::wait(param_0, param_1, param_2),
+        SysCall::EventSet => {
+            let set = event::set(param_0, param_1);
+            scheduler::cur().increment_mepc();
+            Some(to_ret(set))
+        }
+        SysCall::EventClear => {
+            let clear = event::clear(param_0, param_1);
+            scheduler::cur().increment_mepc();
+            Some(to_ret(clear))
+        }
+        SysCall::BlockRead => {
+            block::sys_request(block::Op::Read, param_0 as u64, param_1 as *mut u8)
+        }
+        SysCall::BlockWrite => {
+            block::sys_request(block::Op::Write, param_0 as u64, param_1 as *mut u8)
+        }
+        SysCall::Open => {
+            let cur = scheduler::cur();
+            let open = user_slice(param_0, param_1)
+                .and_then(|path| Descriptor::open(cur, path))
+                .and_then(|descriptor| cur.open_fd(descriptor));
+            cur.increment_mepc();
+            Some(to_ret(open))
+        }
+        SysCall::Stat => {
+            let stat =
+                user_slice(param_0, param_1).and_then(|path| stat(path, param_2 as *mut Stat));
             scheduler::cur().increment_mepc();
-            Some(open as usize)
+            Some(to_ret(stat.map(|_| 0)))
         }
-        SysCall::UartClose => {
-            let close = uart::close(scheduler::cur());
+        SysCall::ReadDir => {
+            let read_dir = user_slice_mut(param_1, param_2).and_then(|buf| read_dir(param_0, buf));
             scheduler::cur().increment_mepc();
-            Some(close as usize)
+            Some(to_ret(read_dir))
+        }
+        SysCall::Create => {
+            let create = user_slice(param_0, param_1).and_then(|path| create(path, param_2));
+            scheduler::cur().increment_mepc();
+            Some(to_ret(create.map(|_| 0)))
+        }
+        SysCall::Remove => {
+            let remove = user_slice(param_0, param_1).and_then(vfs::remove);
+            scheduler::cur().increment_mepc();
+            Some(to_ret(remove.map(|_| 0)))
+        }
+        SysCall::GetRandom => {
+            let fill = user_slice_mut(param_0, param_1).map(|buf| {
+                random::fill(buf);
+                buf.len()
+            });
+            scheduler::cur().increment_mepc();
+            Some(to_ret(fill))
+        }
+        SysCall::GetWallTime => {
+            scheduler::cur().increment_mepc();
+            Some(rtc::now() as usize)
+        }
+        SysCall::WaitWallTime => alarm::wait(param_0 as u64),
+        SysCall::Shutdown => {
+            crate::println!("\n\n## Shutdown with code: {} ##", param_0 as u16);
+            test_finisher::shutdown(param_0 as u16)
         }
+        SysCall::Reboot => {
+            crate::println!("\n\n## Reboot ##");
+            test_finisher::reboot()
+        }
+        SysCall::ThreadCreate => {
+            let create = thread::create(param_0, param_1, param_2);
+            scheduler::cur().increment_mepc();
+            Some(to_ret(create))
+        }
+        SysCall::ThreadExit => {
+            thread::exit(param_0);
+            None
+        }
+        SysCall::ThreadJoin => thread::join(param_0),
     }
 }
 
-unsafe fn print_string(str_ptr: usize, size: usize) {
-    let mut str_ptr = str_ptr as *const u8;
-    for _ in 0..size {
-        let char = MemoryMapping::<u8>::new(str_ptr as usize).read();
-        uart::print_char(char as char);
-        str_ptr = str_ptr.add(1);
-    }
+/// Returns the user memory at `ptr` as a slice.
+/// Fails if the memory is not accessible by the current user prog.
+fn user_slice<'a>(ptr: usize, len: usize) -> Result<&'a [u8], SysCallError> {
+    check_user_memory(ptr, len)?;
+    Ok(unsafe { core::slice::from_raw_parts(ptr as *const u8, len) })
 }
 
-/// Returns [Some] if user prog holds uart and blocks the process.
-/// Returns [None] otherwise.
-fn get_char() -> Option<usize> {
-    let user_prog = scheduler::cur();
-    if !uart::is_open(user_prog) {
-        return Some(0);
+/// Returns the user memory at `ptr` as a mutable slice.
+/// Fails if the memory is not accessible by the current user prog.
+fn user_slice_mut<'a>(ptr: usize, len: usize) -> Result<&'a mut [u8], SysCallError> {
+    check_user_memory(ptr, len)?;
+    Ok(unsafe { core::slice::from_raw_parts_mut(ptr as *mut u8, len) })
+}
+
+/// Checks that the memory at `ptr` belongs to the current user prog or one of its shared memory
+/// regions. The kernel runs in machine mode, the pmp does not protect its memory.
+pub fn check_user_memory(ptr: usize, len: usize) -> Result<(), SysCallError> {
+    match scheduler::cur().can_access(ptr, len) {
+        true => Ok(()),
+        false => Err(SysCallError::InvalidArgument),
     }
-    if let Some(char) = uart::get_char() {
-        return Some(char as usize);
+}
+
+/// Skips the `ecall` and returns the error.
+fn fail(err: SysCallError) -> Option<usize> {
+    scheduler::cur().increment_mepc();
+    Some(to_ret(Err(err)))
+}
+
+/// Finishes an I/O operation of the current user prog.
+/// Returns [None] and blocks the user prog if the operation has to wait.
+/// The `ecall` is not skipped in this case, so the system call is repeated once the user prog is rdy.
+fn finish_io(io: Result<Io, SysCallError>) -> Option<Result<usize, SysCallError>> {
+    let cur = scheduler::cur();
+    match io {
+        Ok(Io::Blocked(reason)) => {
+            cur.set_blocked(reason);
+            sys_yield();
+            None
+        }
+        Ok(Io::Done(count)) => {
+            cur.increment_mepc();
+            Some(Ok(count))
+        }
+        Err(err) => {
+            cur.increment_mepc();
+            Some(Err(err))
+        }
     }
-    scheduler::cur().set_blocked(scheduler::Reason::Uart);
-    sys_yield();
-    None
 }
 
-fn exit() {
+fn read(fd: usize, buf: &mut [u8]) -> Option<Result<usize, SysCallError>> {
+    let cur = scheduler::cur();
+    finish_io(cur.fd(fd).and_then(|mut descriptor| {
+        let io = descriptor.read(cur, buf)?;
+        cur.set_fd(fd, descriptor)?;
+        Ok(io)
+    }))
+}
+
+fn write(fd: usize, buf: &[u8]) -> Option<Result<usize, SysCallError>> {
     let cur = scheduler::cur();
-    uart::close(cur);
-    let prog_info = cur.prog_info();
-    scheduler::end_prog(scheduler::cur());
-    scheduler::init_prog(prog_info);
+    finish_io(cur.fd(fd).and_then(|mut descriptor| {
+        let io = descriptor.write(buf)?;
+        cur.set_fd(fd, descriptor)?;
+        Ok(io)
+    }))
+}
+
+fn read_dir(fd: usize, buf: &mut [u8]) -> Result<usize, SysCallError> {
+    let cur = scheduler::cur();
+    let mut descriptor = cur.fd(fd)?;
+    let len = descriptor.read_dir(buf)?;
+    cur.set_fd(fd, descriptor)?;
+    Ok(len)
+}
+
+/// Writes the status of the entry at the path to `stat` in user memory, it may be unaligned.
+fn stat(path: &[u8], stat: *mut Stat) -> Result<(), SysCallError> {
+    check_user_memory(stat as usize, core::mem::size_of::<Stat>())?;
+    let res = vfs::stat(path)?;
+    unsafe { stat.write_unaligned(res) };
+    Ok(())
+}
+
+fn create(path: &[u8], kind: usize) -> Result<(), SysCallError> {
+    let kind = match kind {
+        0 => FileKind::File,
+        1 => FileKind::Dir,
+        _ => return Err(SysCallError::InvalidArgument),
+    };
+    vfs::create(path, kind)
+}
+
+/// Reads a single char from stdin.
+/// Returns 0 if stdin cannot be read, e.g. when the user prog has not opened `/dev/uart0`.
+fn get_char() -> Option<usize> {
+    let mut char = [0];
+    read(STDIN, &mut char).map(|res| match res {
+        Ok(1) => char[0] as usize,
+        _ => 0,
+    })
+}
+
+/// The user prog is cleaned up and restarted by the reaper.
+fn exit() {
+    scheduler::cur().set_exited();
+    reaper::wake();
     sys_yield();
 }
 
-fn sys_yield() {
-    let next =
-        scheduler::next().expect("No next user prog for system yield. Idle task not implemented");
-    scheduler::switch(next);
+pub fn sys_yield() {
+    scheduler::schedule();
 }
@@ -0,0 +1,65 @@
+//! thread -- Threads of user progs.
+//!
+//! A thread starts at its entry with the argument in `a0` on a stack provided by the user prog.
+//! Its TLS block and its registers are placed at the top of that stack, `tp` points at the block.
+//! The memory and pmp region belong to the user prog.
+
+use riscv_utils::*;
+
+use crate::{
+    hardware::stack::{Stack, FRAME_SIZE},
+    reaper, scheduler,
+    sys_call::sys_yield,
+};
+
+/// Adds a thread to the current user prog which starts at `entry` with `arg` in `a0`.
+/// `stack` is the initial stack pointer, it has to be 16 byte aligned.
+/// Returns the id of the thread.
+pub fn create(entry: usize, stack: usize, arg: usize) -> Result<usize, SysCallError> {
+    let cur = scheduler::cur();
+    let info = cur.prog_info();
+    if !(info.mem_start..info.mem_end).contains(&entry)
+        || !stack.is_multiple_of(16)
+        || stack > info.mem_end
+    {
+        return Err(SysCallError::InvalidArgument);
+    }
+    let tls = cur.tls();
+    let tp = match tls.block_below(stack) {
+        Some(tp) if tp >= info.mem_start + FRAME_SIZE => tp,
+        _ => return Err(SysCallError::InvalidArgument),
+    };
+    let sp = tp - FRAME_SIZE;
+    unsafe {
+        tls.init_block(tp);
+        let mut frame = Stack::empty(sp);
+        frame.set_a0(arg);
+        frame.set_tp(tp);
+        frame.write();
+    }
+    cur.add_thread(entry, sp).map(|thread| thread.thread())
+}
+
+/// Exits the current thread with the code. The user prog exits with its last thread.
+pub fn exit(code: usize) {
+    if scheduler::cur().exit_thread(code) {
+        reaper::wake();
+    }
+    sys_yield();
+}
+
+/// Waits for the thread of the current user prog with the id to exit.
+///
+/// Returns [None] if the return value is written once the thread exits.
+pub fn join(thread: usize) -> Option<usize> {
+    let cur = scheduler::cur();
+    cur.increment_mepc();
+    match cur.join_thread(thread) {
+        Ok(Some(code)) => Some(code),
+        Ok(None) => {
+            sys_yield();
+            None
+        }
+        Err(err) => Some(to_ret(Err(err))),
+    }
+}
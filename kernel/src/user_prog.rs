@@ -1,18 +1,28 @@
 //! The user program descriptions with a fixed memory location.
+//! The program images are loaded from the initramfs by path.
+
+use enum_matching::EnumTryFrom;
+
+use crate::{elf, initramfs};
 
 pub const USER1: Info = Info {
     id: Id::User1,
-    boot_mepc: 0x80100000,
+    path: "/bin/user_1",
+    mem_start: 0x80100000,
+    mem_end: 0x80200000,
     pmp_idx: 0,
 };
 
 pub const USER2: Info = Info {
     id: Id::User2,
-    boot_mepc: 0x80200000,
+    path: "/bin/user_2",
+    mem_start: 0x80200000,
+    mem_end: 0x80300000,
     pmp_idx: 1,
 };
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// The id is used as the pid of the user program, e.g. for ipc.
+#[derive(Clone, Copy, Debug, PartialEq, EnumTryFrom)]
 pub enum Id {
     User1,
     User2,
@@ -20,6 +30,18 @@ pub enum Id {
 #[derive(PartialEq, Clone, Copy)]
 pub struct Info {
     pub id: Id,
-    pub boot_mepc: usize,
+    /// Path of the program image in the initramfs.
+    pub path: &'static str,
+    pub mem_start: usize,
+    pub mem_end: usize,
     pub pmp_idx: usize,
 }
+impl Info {
+    /// Loads the program image into the memory of the user program.
+    pub fn load(&self) -> elf::Image {
+        let image = initramfs::find(self.path.as_bytes())
+            .unwrap_or_else(|| panic!("Program image: {} not found in the initramfs", self.path));
+        elf::load(image.data(), self.mem_start..self.mem_end)
+            .unwrap_or_else(|err| panic!("Failed to load program image: {}: {:?}", self.path, err))
+    }
+}
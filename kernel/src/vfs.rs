@@ -0,0 +1,300 @@
+//! vfs -- Virtual file system.
+//!
+//! Every file system is mounted at a path of the mount table. A path is resolved by removing
+//! `.` and `..` components first and passing the rest behind the longest matching mount point
+//! to its file system. There is no working directory, so relative paths start at the root.
+
+use riscv_utils::{FileKind, Stat, SysCallError, PATH_LEN};
+
+use crate::{
+    dev::{self, Device},
+    fat32,
+    fd::Io,
+    hardware::sync::Protected,
+    initramfs,
+    scheduler::Prog,
+};
+
+const MOUNTS: usize = 4;
+
+static MOUNT_TABLE: Protected<[Option<Mount>; MOUNTS]> = Protected::new([None; MOUNTS]);
+
+/// Mounts the initramfs as root, the device nodes and the file system of the block device.
+pub fn init() {
+    mount(b"", FileSystem::Initramfs);
+    mount(b"/dev", FileSystem::Dev);
+    if fat32::init() {
+        mount(b"/disk", FileSystem::Fat32);
+    }
+}
+
+/// Mounts the file system at the path. The path has to be canonical, e.g. `/disk`.
+/// The root directory is the empty path.
+pub fn mount(path: &'static [u8], fs: FileSystem) {
+    let mut mount_table = MOUNT_TABLE.lock();
+    let mount = mount_table
+        .iter_mut()
+        .find(|mount| mount.is_none())
+        .expect("The mount table is full");
+    *mount = Some(Mount { path, fs });
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FileSystem {
+    Initramfs,
+    Fat32,
+    Dev,
+}
+impl FileSystem {
+    fn lookup(&self, path: &[u8]) -> Result<Inode, SysCallError> {
+        match self {
+            FileSystem::Initramfs => initramfs::find(path)
+                .map(Inode::Initramfs)
+                .ok_or(SysCallError::NotFound),
+            FileSystem::Fat32 => Ok(Inode::Fat32(fat32::find(path)?)),
+            FileSystem::Dev if path.is_empty() => Ok(Inode::DevDir),
+            FileSystem::Dev => dev::find(path)
+                .map(Inode::Device)
+                .ok_or(SysCallError::NotFound),
+        }
+    }
+    fn create(&self, path: &[u8], kind: FileKind) -> Result<(), SysCallError> {
+        match self {
+            FileSystem::Fat32 => {
+                fat32::create(path, kind)?;
+                Ok(())
+            }
+            FileSystem::Initramfs | FileSystem::Dev => Err(SysCallError::NotSupported),
+        }
+    }
+    fn remove(&self, path: &[u8]) -> Result<(), SysCallError> {
+        match self {
+            FileSystem::Fat32 => Ok(fat32::remove(path)?),
+            FileSystem::Initramfs | FileSystem::Dev => Err(SysCallError::NotSupported),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Mount {
+    path: &'static [u8],
+    fs: FileSystem,
+}
+
+/// A canonical absolute path without `.` and `..` components and without a trailing slash.
+/// The root directory is the empty path.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Path {
+    buf: [u8; PATH_LEN],
+    len: usize,
+}
+impl Path {
+    pub fn new(path: &[u8]) -> Result<Path, SysCallError> {
+        let mut buf = [0; PATH_LEN];
+        let mut len = 0;
+        for component in path.split(|&byte| byte == b'/') {
+            match component {
+                b"" | b"." => {}
+                b".." => len = parent(&buf[..len]).len(),
+                _ => {
+                    let end = len + 1 + component.len();
+                    if end > PATH_LEN {
+                        return Err(SysCallError::InvalidArgument);
+                    }
+                    buf[len] = b'/';
+                    buf[len + 1..end].copy_from_slice(component);
+                    len = end;
+                }
+            }
+        }
+        Ok(Path { buf, len })
+    }
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+    /// Returns the mounted file system and the path relative to its mount point.
+    fn mount(&self) -> Result<(FileSystem, &[u8]), SysCallError> {
+        let path = self.as_bytes();
+        MOUNT_TABLE
+            .lock()
+            .iter()
+            .flatten()
+            .filter(|mount| is_below(path, mount.path))
+            .max_by_key(|mount| mount.path.len())
+            .map(|mount| (mount.fs, &path[mount.path.len()..]))
+            .ok_or(SysCallError::NotFound)
+    }
+}
+
+/// A resolved path.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Dentry {
+    path: Path,
+    inode: Inode,
+}
+impl Dentry {
+    pub fn inode(&self) -> Inode {
+        self.inode
+    }
+    /// Copies the name of the entry of the directory at the index into the buffer.
+    /// Mount points in the directory are listed first. Returns [None] past the last entry.
+    pub fn child(&self, idx: usize, buf: &mut [u8]) -> Result<Option<usize>, SysCallError> {
+        let path = self.path.as_bytes();
+        let mount_table = MOUNT_TABLE.lock();
+        let mut mount_points = mount_table
+            .iter()
+            .flatten()
+            .filter(|mount| !mount.path.is_empty() && parent(mount.path) == path);
+        let mount_count = mount_points.clone().count();
+        if let Some(mount) = mount_points.nth(idx) {
+            return copy_name(file_name(mount.path), buf).map(Some);
+        }
+        mount_table.unlock();
+        self.inode.child(idx - mount_count, buf)
+    }
+}
+
+/// A file, directory or device of a mounted file system.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Inode {
+    Initramfs(initramfs::Entry),
+    Fat32(fat32::Node),
+    /// The root directory of the device nodes.
+    DevDir,
+    Device(Device),
+}
+impl Inode {
+    pub fn stat(&self) -> Result<Stat, SysCallError> {
+        match self {
+            Inode::Initramfs(entry) => Ok(entry.stat()),
+            Inode::Fat32(node) => Ok(fat32::stat(*node)?),
+            Inode::DevDir => Ok(Stat {
+                size: 0,
+                kind: FileKind::Dir,
+            }),
+            Inode::Device(_) => Ok(Stat {
+                size: 0,
+                kind: FileKind::File,
+            }),
+        }
+    }
+    /// Called for every open of the inode. Devices and FAT32 nodes are held by the user prog
+    /// until [Inode::release].
+    pub fn open(&self, prog: Prog) -> Result<(), SysCallError> {
+        match self {
+            Inode::Device(device) => device.open(prog),
+            Inode::Fat32(node) => Ok(fat32::open(*node, prog)?),
+            Inode::Initramfs(_) | Inode::DevDir => Ok(()),
+        }
+    }
+    /// Called when the last file descriptor of the user prog referencing the inode is closed.
+    pub fn release(&self, prog: Prog) {
+        match self {
+            Inode::Device(device) => device.release(prog),
+            Inode::Fat32(node) => fat32::release(*node, prog),
+            Inode::Initramfs(_) | Inode::DevDir => {}
+        }
+    }
+    /// Reads from the offset into the buffer. The offset is ignored by devices.
+    pub fn read(&self, prog: Prog, offset: usize, buf: &mut [u8]) -> Result<Io, SysCallError> {
+        match self {
+            Inode::Initramfs(entry) => {
+                let data = entry.data().get(offset..).unwrap_or_default();
+                let count = buf.len().min(data.len());
+                buf[..count].copy_from_slice(&data[..count]);
+                Ok(Io::Done(count))
+            }
+            Inode::Fat32(node) => Ok(Io::Done(fat32::read(*node, offset, buf)?)),
+            Inode::Device(device) => device.read(prog, buf),
+            Inode::DevDir => Err(SysCallError::InvalidArgument),
+        }
+    }
+    /// Writes the buffer at the offset. The offset is ignored by devices.
+    pub fn write(&self, offset: usize, buf: &[u8]) -> Result<Io, SysCallError> {
+        match self {
+            Inode::Initramfs(_) => Err(SysCallError::NotPermitted),
+            Inode::Fat32(node) => Ok(Io::Done(fat32::write(*node, offset, buf)?)),
+            Inode::Device(device) => device.write(buf),
+            Inode::DevDir => Err(SysCallError::InvalidArgument),
+        }
+    }
+    /// Copies the name of the entry of the directory at the index into the buffer.
+    /// Returns [None] past the last entry.
+    fn child(&self, idx: usize, buf: &mut [u8]) -> Result<Option<usize>, SysCallError> {
+        match self {
+            Inode::Initramfs(entry) => entry
+                .child(idx)
+                .map(|child| copy_name(child.file_name(), buf))
+                .transpose(),
+            Inode::Fat32(node) => {
+                let mut name = [0; fat32::NAME_LEN];
+                match fat32::child(*node, idx, &mut name)? {
+                    Some(len) => copy_name(&name[..len], buf).map(Some),
+                    None => Ok(None),
+                }
+            }
+            Inode::DevDir => dev::name(idx).map(|name| copy_name(name, buf)).transpose(),
+            Inode::Device(_) => Err(SysCallError::InvalidArgument),
+        }
+    }
+}
+
+/// Resolves the path.
+pub fn lookup(path: &[u8]) -> Result<Dentry, SysCallError> {
+    let path = Path::new(path)?;
+    let (fs, rel_path) = path.mount()?;
+    let inode = fs.lookup(rel_path)?;
+    Ok(Dentry { path, inode })
+}
+
+pub fn stat(path: &[u8]) -> Result<Stat, SysCallError> {
+    lookup(path)?.inode.stat()
+}
+
+/// Creates an empty file or directory.
+pub fn create(path: &[u8], kind: FileKind) -> Result<(), SysCallError> {
+    let path = Path::new(path)?;
+    let (fs, rel_path) = path.mount()?;
+    if rel_path.is_empty() {
+        return Err(SysCallError::AlreadyExists);
+    }
+    fs.create(rel_path, kind)
+}
+
+/// Deletes the file or the empty directory. Mount points and open files cannot be deleted.
+pub fn remove(path: &[u8]) -> Result<(), SysCallError> {
+    let path = Path::new(path)?;
+    let (fs, rel_path) = path.mount()?;
+    if rel_path.is_empty() {
+        return Err(SysCallError::NotPermitted);
+    }
+    fs.remove(rel_path)
+}
+
+/// Returns true if the canonical path is the mount point or below it.
+fn is_below(path: &[u8], mount_point: &[u8]) -> bool {
+    match path.strip_prefix(mount_point) {
+        Some(rest) => rest.is_empty() || rest.starts_with(b"/"),
+        None => false,
+    }
+}
+
+fn parent(path: &[u8]) -> &[u8] {
+    let len = path.iter().rposition(|&byte| byte == b'/').unwrap_or(0);
+    &path[..len]
+}
+
+fn file_name(path: &[u8]) -> &[u8] {
+    let start = path
+        .iter()
+        .rposition(|&byte| byte == b'/')
+        .map_or(0, |idx| idx + 1);
+    &path[start..]
+}
+
+fn copy_name(name: &[u8], buf: &mut [u8]) -> Result<usize, SysCallError> {
+    buf.get_mut(..name.len())
+        .ok_or(SysCallError::InvalidArgument)?
+        .copy_from_slice(name);
+    Ok(name.len())
+}
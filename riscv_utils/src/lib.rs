@@ -3,7 +3,10 @@
 #![no_std]
 #![allow(unused)]
 mod sys_call;
-pub use sys_call::SysCall;
+pub use sys_call::{
+    from_ret, to_ret, FileKind, Message, Stat, SysCall, SysCallError, MSG_WORDS, PATH_LEN,
+    SECTOR_SIZE, SHM_SIZE, STDERR, STDIN, STDOUT,
+};
 
 pub type RegisterEntry = (usize, bool);
 ///`mpp`: sets previous privilege mode to user-mode so modules run only in U-mode after the setup.
@@ -11,6 +14,9 @@ pub const MSTATUS_MPP_U: (RegisterEntry, RegisterEntry) = ((11, false), (12, fal
 ///`mie`: machine-mode interrupt enable
 pub const MSTATUS_MIE: RegisterEntry = (3, true);
 
+/// `mode` of `mtvec`: interrupts jump to `base + 4 * cause`, exceptions to `base`.
+pub const MTVEC_VECTORED: usize = 1;
+
 /// `meie`: external machine-mode interrupt enable
 pub const MIE_MEIE: RegisterEntry = (11, true);
 /// `mtie`: timer machine-mode interrupt enable
@@ -25,6 +31,8 @@ pub const SIE_STIE: RegisterEntry = (5, true);
 /// `ssie`: software supervisor-mode interrupt enable
 pub const SIE_SSIE: RegisterEntry = (1, true);
 
+/// `mcause` value for a software interrupt, raised by another hart.
+pub const MCAUSE_INTERRUPT_SOFTWARE: usize = 3;
 /// `mcause` value for a timer interrupt.
 pub const MCAUSE_INTERRUPT_TIMER: usize = 7;
 /// `mcause` value for an extern interrupt, like the plic.
@@ -37,6 +45,8 @@ pub const MCAUSE_EXCEPTION_II: usize = 2;
 pub const MCAUSE_EXCEPTION_LAF: usize = 5;
 /// `mcause` value for an ecall exception.
 pub const MCAUSE_EXCEPTION_ECALL: usize = 8;
+/// `mcause` value for an ecall exception from machine mode, raised by kernel threads.
+pub const MCAUSE_EXCEPTION_ECALL_M: usize = 11;
 
 /// A convenient macro to avoid writing assembly code for reading machine register.
 ///
@@ -1,5 +1,12 @@
 use enum_matching::EnumTryFrom;
 
+/// Standard input file descriptor.
+pub const STDIN: usize = 0;
+/// Standard output file descriptor.
+pub const STDOUT: usize = 1;
+/// Standard error file descriptor.
+pub const STDERR: usize = 2;
+
 /// System calls enum.
 /// Required for kernel and user prog to sync the system call index.
 #[derive(EnumTryFrom)]
@@ -8,8 +15,122 @@ pub enum SysCall {
     PrintChar,
     PrintNum,
     GetChar,
-    UartOpen,
-    UartClose,
     Yield = 23,
     Exit = 42,
+    Read,
+    Write,
+    Close,
+    Dup2,
+    Send,
+    Receive,
+    Call,
+    ShmOpen,
+    ShmClose,
+    FutexWait,
+    FutexWake,
+    SemCreate,
+    SemWait,
+    SemPost,
+    EventCreate,
+    EventWait,
+    EventSet,
+    EventClear,
+    BlockRead,
+    BlockWrite,
+    Open,
+    Stat,
+    ReadDir,
+    Create,
+    Remove,
+    GetRandom,
+    GetWallTime,
+    WaitWallTime,
+    Shutdown,
+    Reboot,
+    ThreadCreate,
+    ThreadExit,
+    ThreadJoin,
+}
+
+/// Size of a shared memory region in bytes.
+pub const SHM_SIZE: usize = 0x1000;
+
+/// Number of words in an ipc message.
+pub const MSG_WORDS: usize = 4;
+
+/// An ipc message. Carried in the registers `a1` to `a4`.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Message(pub [usize; MSG_WORDS]);
+
+/// System call errors.
+/// Returned to the user prog as negative numbers in `a0`.
+#[derive(EnumTryFrom, Clone, Copy, PartialEq, Debug)]
+pub enum SysCallError {
+    /// The file descriptor is not open or out of range.
+    BadFd = -1,
+    /// The resource is held by a different user prog.
+    NotPermitted = -2,
+    /// A parameter is not valid for the system call.
+    InvalidArgument = -3,
+    /// The operation is not supported by the resource.
+    NotSupported = -4,
+    /// No free slot is available, e.g. in the file descriptor table.
+    NoSpace = -5,
+    /// No user prog exists with the pid.
+    NoSuchProg = -6,
+    /// The value changed before the user prog could block.
+    Again = -7,
+    /// The timeout expired before the user prog was woken.
+    TimedOut = -8,
+    /// The device failed to process the request.
+    Io = -9,
+    /// No file or directory exists with the path.
+    NotFound = -10,
+    /// A file or directory already exists with the path.
+    AlreadyExists = -11,
+    /// The directory still contains entries.
+    DirectoryNotEmpty = -12,
+    /// The resource is in use, e.g. a file opened by a user prog.
+    Busy = -13,
+}
+
+/// Size of a sector of a block device in bytes.
+pub const SECTOR_SIZE: usize = 512;
+
+/// Maximum length of a path in bytes.
+pub const PATH_LEN: usize = 100;
+
+/// The kind of a file system entry.
+#[repr(usize)]
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub enum FileKind {
+    #[default]
+    File,
+    Dir,
+}
+
+/// Status of a file system entry. Written by the kernel for [SysCall::Stat].
+#[repr(C)]
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Stat {
+    /// Size in bytes. 0 for directories.
+    pub size: usize,
+    pub kind: FileKind,
+}
+
+/// Converts a system call result into the value returned in `a0`.
+pub fn to_ret(result: Result<usize, SysCallError>) -> usize {
+    match result {
+        Ok(val) => val,
+        Err(err) => err as isize as usize,
+    }
+}
+
+/// Converts the value returned in `a0` into a system call result.
+pub fn from_ret(ret: usize) -> Result<usize, SysCallError> {
+    let ret = ret as isize;
+    if ret >= 0 {
+        return Ok(ret as usize);
+    }
+    Err(SysCallError::try_from(ret).unwrap_or_else(|_| panic!("Illegal syscall error: {}", ret)))
 }
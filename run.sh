@@ -0,0 +1,14 @@
+#!/bin/sh
+# Packs the initramfs and runs the kernel with QEMU. Used as cargo runner.
+# Usage: ./run.sh <kernel> [qemu args..]
+set -e
+dir=$(dirname "$1")
+root="$dir/initramfs"
+rm -rf "$root"
+mkdir -p "$root/bin"
+cp -R initramfs/. "$root"
+cp "$dir/user_1" "$dir/user_2" "$root/bin"
+tar --format=ustar -cf "$dir/initramfs.tar" -C "$root" .
+exec qemu-system-riscv64 -nographic -machine virt -smp 2 -bios none \
+    -device loader,file="$dir/initramfs.tar",addr=0x80400000 \
+    -kernel "$@"
@@ -7,19 +7,17 @@ use user_shared::*;
 
 #[no_mangle]
 extern "C" fn main() {
+    print_motd();
     if sys::get_char().is_some() {
         sys::print("\nu1: Is not allowed to get a char!");
     }
-    if sys::uart_close() {
-        sys::print("\nu1: Is not allowed to close uart!");
-    }
     for i in 1..5000001 {
         if i % 1000000 == 0 {
             sys::print("\n");
             sys::print_num(i / 1000000);
         }
     }
-    if sys::uart_open() {
+    if let Ok(uart) = sys::open("/dev/uart0") {
         sys::print("\nuart is open!");
         for i in 5000001..10000001 {
             if i % 1000000 == 0 {
@@ -42,12 +40,28 @@ extern "C" fn main() {
             }
             sys::print_char(char);
         }
-        if !sys::uart_open() {
-            sys::print("\nu1: Uart should be open!");
+        match sys::open("/dev/uart0") {
+            Ok(fd) => {
+                sys::close(fd).ok();
+            }
+            Err(_) => sys::print("\nu1: Uart should be open!"),
         }
-        if !sys::uart_close() {
+        if sys::close(uart).is_err() {
             sys::print("\nu1: should be allowed to close uart!");
         }
     }
     sys::exit();
 }
+
+/// Prints the message of the day from the initramfs.
+fn print_motd() {
+    let Ok(fd) = sys::open("/etc/motd") else {
+        sys::print("\nu1: /etc/motd not found!");
+        return;
+    };
+    let mut buf = [0; 64];
+    while let Ok(count @ 1..) = sys::read(fd, &mut buf) {
+        sys::write(sys::STDOUT, &buf[..count]).ok();
+    }
+    sys::close(fd).ok();
+}
@@ -10,16 +10,13 @@ extern "C" fn main() {
     if sys::get_char().is_some() {
         sys::print("\nu2: Is not allowed to get a char!");
     }
-    if sys::uart_close() {
-        sys::print("\nu2: Is not allowed to close uart!");
-    }
     for i in 1..5000001 {
         if i % 1000000 == 0 {
             sys::print("\n        ");
             sys::print_num(i / 1000000);
         }
     }
-    if sys::uart_open() {
+    if let Ok(uart) = sys::open("/dev/uart0") {
         sys::print("\n        uart is open!");
         for i in 5000001..10000001 {
             if i % 1000000 == 0 {
@@ -42,10 +39,13 @@ extern "C" fn main() {
             }
             sys::print_char(char);
         }
-        if !sys::uart_open() {
-            sys::print("\nu2: Uart should be open!");
+        match sys::open("/dev/uart0") {
+            Ok(fd) => {
+                sys::close(fd).ok();
+            }
+            Err(_) => sys::print("\nu2: Uart should be open!"),
         }
-        if !sys::uart_close() {
+        if sys::close(uart).is_err() {
             sys::print("\nu2: should be allowed to close uart!");
         }
     }
@@ -4,4 +4,6 @@
 
 pub mod asm;
 pub mod panic_handler;
+pub mod sync;
 pub mod sys_call;
+pub mod thread;
@@ -0,0 +1,134 @@
+//! Blocking synchronization primitives based on futexes.
+//!
+//! The primitives can be placed in a shared memory region to synchronize different user programs.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::sys_call::{futex_wait, futex_wake};
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/// Locked with possibly blocked waiters.
+const CONTENDED: u32 = 2;
+
+pub struct Mutex<T> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            state: AtomicU32::new(UNLOCKED),
+            data: UnsafeCell::new(data),
+        }
+    }
+    /// Blocks until acquiring the lock.
+    ///
+    /// # Unlocking
+    ///
+    /// The lock is unlocked when the [MutexGuard] is dropped.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+                futex_wait(&self.state, CONTENDED, 0).ok();
+            }
+        }
+        MutexGuard { mutex: self }
+    }
+    fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(&self.state, 1);
+        }
+    }
+}
+unsafe impl<T> Sync for Mutex<T> {}
+
+/// Dropping the [MutexGuard] will unlock the [Mutex].
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+pub struct Condvar {
+    seq: AtomicU32,
+}
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            seq: AtomicU32::new(0),
+        }
+    }
+    /// Unlocks the mutex and blocks until notified. The mutex is locked again before returning.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let seq = self.seq.load(Ordering::Relaxed);
+        let mutex = guard.mutex;
+        drop(guard);
+        futex_wait(&self.seq, seq, 0).ok();
+        mutex.lock()
+    }
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        futex_wake(&self.seq, 1);
+    }
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        futex_wake(&self.seq, usize::MAX);
+    }
+}
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Semaphore {
+    count: AtomicU32,
+}
+impl Semaphore {
+    pub const fn new(count: u32) -> Self {
+        Semaphore {
+            count: AtomicU32::new(count),
+        }
+    }
+    /// Blocks until the count is positive and decrements it.
+    pub fn wait(&self) {
+        loop {
+            let count = self.count.load(Ordering::Relaxed);
+            if count == 0 {
+                futex_wait(&self.count, 0, 0).ok();
+            } else if self
+                .count
+                .compare_exchange(count, count - 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return;
+            }
+        }
+    }
+    /// Increments the count and wakes a waiting user prog.
+    pub fn post(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        futex_wake(&self.count, 1);
+    }
+}
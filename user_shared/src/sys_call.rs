@@ -2,15 +2,22 @@
 
 #![allow(dead_code)]
 use core::arch::asm;
+use core::sync::atomic::AtomicU32;
 use riscv_utils as riscv;
-use riscv_utils::SysCall;
+pub use riscv_utils::{FileKind, Stat, SysCallError, SECTOR_SIZE, STDERR, STDIN, STDOUT};
+use riscv_utils::{Message, SysCall};
 
 unsafe fn sys_call(syscall: SysCall, param_0: usize, param_1: usize) -> usize {
+    sys_call_3(syscall, param_0, param_1, 0)
+}
+
+unsafe fn sys_call_3(syscall: SysCall, param_0: usize, param_1: usize, param_2: usize) -> usize {
     let number = syscall as usize;
     riscv::write_function_reg!(
         number => "a7",
         param_0 => "a0",
-        param_1 => "a1"
+        param_1 => "a1",
+        param_2 => "a2"
     );
     asm!("ecall");
     let output;
@@ -18,13 +25,29 @@ unsafe fn sys_call(syscall: SysCall, param_0: usize, param_1: usize) -> usize {
     output
 }
 
+/// System call carrying an ipc message in the registers `a1` to `a4`.
+/// The message is overwritten with the message returned by the kernel.
+unsafe fn sys_call_msg(syscall: SysCall, param_0: usize, msg: &mut Message) -> usize {
+    let output;
+    asm!(
+        "ecall",
+        inlateout("a0") param_0 => output,
+        inlateout("a1") msg.0[0] => msg.0[0],
+        inlateout("a2") msg.0[1] => msg.0[1],
+        inlateout("a3") msg.0[2] => msg.0[2],
+        inlateout("a4") msg.0[3] => msg.0[3],
+        in("a7") syscall as usize,
+    );
+    output
+}
+
 pub fn print_char(char: char) {
     unsafe {
         sys_call(SysCall::PrintChar, char as usize, 0);
     }
 }
 
-/// Requires uart to be open. Returns 'None' otherwise.
+/// Requires `/dev/uart0` to be open. Returns 'None' otherwise.
 pub fn get_char() -> Option<char> {
     unsafe {
         let res = sys_call(SysCall::GetChar, 0, 0);
@@ -62,10 +85,293 @@ pub fn sys_yield() {
     }
 }
 
-pub fn uart_open() -> bool {
-    unsafe { sys_call(SysCall::UartOpen, 0, 0) != 0 }
+/// Reads from the file descriptor into the buffer. Returns the number of bytes read.
+/// Blocks until at least one byte is available.
+pub fn read(fd: usize, buf: &mut [u8]) -> Result<usize, SysCallError> {
+    unsafe {
+        riscv::from_ret(sys_call_3(
+            SysCall::Read,
+            fd,
+            buf.as_mut_ptr() as usize,
+            buf.len(),
+        ))
+    }
+}
+
+/// Writes the buffer to the file descriptor. Returns the number of bytes written.
+pub fn write(fd: usize, buf: &[u8]) -> Result<usize, SysCallError> {
+    unsafe {
+        riscv::from_ret(sys_call_3(
+            SysCall::Write,
+            fd,
+            buf.as_ptr() as usize,
+            buf.len(),
+        ))
+    }
+}
+
+pub fn close(fd: usize) -> Result<(), SysCallError> {
+    unsafe { riscv::from_ret(sys_call(SysCall::Close, fd, 0)).map(|_| ()) }
+}
+
+/// Makes `new_fd` a copy of `old_fd`. Closes `new_fd` first if it is open.
+pub fn dup2(old_fd: usize, new_fd: usize) -> Result<usize, SysCallError> {
+    unsafe { riscv::from_ret(sys_call(SysCall::Dup2, old_fd, new_fd)) }
+}
+
+/// Sends the message to the user prog with the pid.
+/// Blocks until the message is received. Returns [SysCallError::NoSuchProg] if the receiver exits.
+pub fn send(pid: usize, msg: Message) -> Result<(), SysCallError> {
+    let mut msg = msg;
+    unsafe { riscv::from_ret(sys_call_msg(SysCall::Send, pid, &mut msg)).map(|_| ()) }
+}
+
+/// Blocks until a message is received. Returns the pid of the sender.
+pub fn receive(msg: &mut Message) -> Result<usize, SysCallError> {
+    unsafe { riscv::from_ret(sys_call_msg(SysCall::Receive, 0, msg)) }
+}
+
+/// Sends the message to the user prog with the pid and waits for the reply.
+/// The message is replaced by the reply. Returns [SysCallError::NoSuchProg] if the receiver exits.
+pub fn call(pid: usize, msg: &mut Message) -> Result<(), SysCallError> {
+    unsafe { riscv::from_ret(sys_call_msg(SysCall::Call, pid, msg)).map(|_| ()) }
+}
+
+/// Opens the shared memory region with the name. The region is created if it does not exist.
+/// Returns the start of the region with the size [riscv::SHM_SIZE].
+pub fn shm_open(name: &str) -> Result<*mut u8, SysCallError> {
+    unsafe {
+        riscv::from_ret(sys_call(
+            SysCall::ShmOpen,
+            name.as_ptr() as usize,
+            name.len(),
+        ))
+        .map(|addr| addr as *mut u8)
+    }
+}
+
+/// Closes the shared memory region. The region must not be accessed afterwards.
+pub fn shm_close(region: *mut u8) -> Result<(), SysCallError> {
+    unsafe { riscv::from_ret(sys_call(SysCall::ShmClose, region as usize, 0)).map(|_| ()) }
+}
+
+/// Blocks while the futex holds the `expected` value until it is woken.
+/// A `timeout` of 0 waits forever, otherwise it is measured in timer ticks.
+///
+/// Returns [SysCallError::Again] if the value differs and [SysCallError::TimedOut] if the timeout expired.
+pub fn futex_wait(futex: &AtomicU32, expected: u32, timeout: usize) -> Result<(), SysCallError> {
+    unsafe {
+        riscv::from_ret(sys_call_3(
+            SysCall::FutexWait,
+            futex.as_ptr() as usize,
+            expected as usize,
+            timeout,
+        ))
+        .map(|_| ())
+    }
+}
+
+/// Wakes up to `count` user progs waiting on the futex. Returns the number of woken user progs.
+pub fn futex_wake(futex: &AtomicU32, count: usize) -> usize {
+    unsafe { sys_call(SysCall::FutexWake, futex.as_ptr() as usize, count) }
+}
+
+/// Returns the handle of the kernel semaphore with the name.
+/// The semaphore is created with the count if it does not exist.
+pub fn sem_create(name: &str, count: usize) -> Result<usize, SysCallError> {
+    unsafe {
+        riscv::from_ret(sys_call_3(
+            SysCall::SemCreate,
+            name.as_ptr() as usize,
+            name.len(),
+            count,
+        ))
+    }
+}
+
+/// Decrements the semaphore or blocks until it is posted.
+/// A `timeout` of 0 waits forever, otherwise it is measured in timer ticks.
+pub fn sem_wait(handle: usize, timeout: usize) -> Result<(), SysCallError> {
+    unsafe { riscv::from_ret(sys_call(SysCall::SemWait, handle, timeout)).map(|_| ()) }
+}
+
+pub fn sem_post(handle: usize) -> Result<(), SysCallError> {
+    unsafe { riscv::from_ret(sys_call(SysCall::SemPost, handle, 0)).map(|_| ()) }
+}
+
+/// Returns the handle of the kernel event with the name.
+/// The event is created without any flags set if it does not exist.
+pub fn event_create(name: &str) -> Result<usize, SysCallError> {
+    unsafe {
+        riscv::from_ret(sys_call(
+            SysCall::EventCreate,
+            name.as_ptr() as usize,
+            name.len(),
+        ))
+    }
+}
+
+/// Blocks until any flag of the mask is set. Returns the set flags of the mask.
+/// A `timeout` of 0 waits forever, otherwise it is measured in timer ticks.
+pub fn event_wait(handle: usize, mask: u32, timeout: usize) -> Result<u32, SysCallError> {
+    unsafe {
+        riscv::from_ret(sys_call_3(
+            SysCall::EventWait,
+            handle,
+            mask as usize,
+            timeout,
+        ))
+        .map(|flags| flags as u32)
+    }
+}
+
+/// Sets the flags and wakes all waiting user progs. Returns all set flags.
+pub fn event_set(handle: usize, flags: u32) -> Result<u32, SysCallError> {
+    unsafe {
+        riscv::from_ret(sys_call(SysCall::EventSet, handle, flags as usize))
+            .map(|flags| flags as u32)
+    }
+}
+
+/// Clears the flags. Returns the remaining flags.
+pub fn event_clear(handle: usize, flags: u32) -> Result<u32, SysCallError> {
+    unsafe {
+        riscv::from_ret(sys_call(SysCall::EventClear, handle, flags as usize))
+            .map(|flags| flags as u32)
+    }
+}
+
+/// Reads the sector of the block device. Blocks until the request is finished.
+pub fn block_read(sector: u64, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), SysCallError> {
+    unsafe {
+        riscv::from_ret(sys_call(
+            SysCall::BlockRead,
+            sector as usize,
+            buf.as_mut_ptr() as usize,
+        ))
+        .map(|_| ())
+    }
+}
+
+/// Writes the sector of the block device. Blocks until the request is finished.
+pub fn block_write(sector: u64, buf: &[u8; SECTOR_SIZE]) -> Result<(), SysCallError> {
+    unsafe {
+        riscv::from_ret(sys_call(
+            SysCall::BlockWrite,
+            sector as usize,
+            buf.as_ptr() as usize,
+        ))
+        .map(|_| ())
+    }
+}
+
+/// Opens the file or directory at the path. Returns the file descriptor.
+pub fn open(path: &str) -> Result<usize, SysCallError> {
+    unsafe { riscv::from_ret(sys_call(SysCall::Open, path.as_ptr() as usize, path.len())) }
+}
+
+/// Returns the status of the file or directory at the path.
+pub fn stat(path: &str) -> Result<Stat, SysCallError> {
+    let mut stat = Stat::default();
+    unsafe {
+        riscv::from_ret(sys_call_3(
+            SysCall::Stat,
+            path.as_ptr() as usize,
+            path.len(),
+            &mut stat as *mut Stat as usize,
+        ))?;
+    }
+    Ok(stat)
+}
+
+/// Copies the name of the next entry of the opened directory into the buffer.
+/// Returns the length of the name or 0 after the last entry.
+pub fn read_dir(fd: usize, buf: &mut [u8]) -> Result<usize, SysCallError> {
+    unsafe {
+        riscv::from_ret(sys_call_3(
+            SysCall::ReadDir,
+            fd,
+            buf.as_mut_ptr() as usize,
+            buf.len(),
+        ))
+    }
+}
+
+/// Creates an empty file or directory at the path. Only paths below `/disk` are writable.
+pub fn create(path: &str, kind: FileKind) -> Result<(), SysCallError> {
+    unsafe {
+        riscv::from_ret(sys_call_3(
+            SysCall::Create,
+            path.as_ptr() as usize,
+            path.len(),
+            kind as usize,
+        ))
+        .map(|_| ())
+    }
+}
+
+/// Deletes the file or the empty directory at the path. Returns [SysCallError::Busy] while it is open.
+pub fn remove(path: &str) -> Result<(), SysCallError> {
+    unsafe {
+        riscv::from_ret(sys_call(
+            SysCall::Remove,
+            path.as_ptr() as usize,
+            path.len(),
+        ))
+        .map(|_| ())
+    }
+}
+
+/// Fills the buffer with cryptographically secure random bytes.
+pub fn get_random(buf: &mut [u8]) {
+    unsafe {
+        sys_call(SysCall::GetRandom, buf.as_mut_ptr() as usize, buf.len());
+    }
+}
+
+/// Returns the wall time in nanoseconds since the Unix epoch.
+pub fn get_wall_time() -> u64 {
+    unsafe { sys_call(SysCall::GetWallTime, 0, 0) as u64 }
+}
+
+/// Blocks until the wall time in nanoseconds since the Unix epoch is reached.
+pub fn wait_wall_time(time: u64) {
+    unsafe {
+        sys_call(SysCall::WaitWallTime, time as usize, 0);
+    }
+}
+
+/// Powers off the machine. QEMU exits with the code, 0 means success.
+pub fn shutdown(code: u16) -> ! {
+    unsafe {
+        sys_call(SysCall::Shutdown, code as usize, 0);
+    }
+    unreachable!("Shutdown returned");
+}
+
+pub fn reboot() -> ! {
+    unsafe {
+        sys_call(SysCall::Reboot, 0, 0);
+    }
+    unreachable!("Reboot returned");
+}
+
+/// Starts a thread of the user prog at `entry` with `arg` in `a0`. Returns the id of the thread.
+/// `stack` is the initial stack pointer of the thread, it has to be 16 byte aligned.
+/// The thread must end with [thread_exit], see [crate::thread::spawn].
+pub fn thread_create(entry: usize, stack: usize, arg: usize) -> Result<usize, SysCallError> {
+    unsafe { riscv::from_ret(sys_call_3(SysCall::ThreadCreate, entry, stack, arg)) }
+}
+
+/// Exits the current thread. The user prog exits with its last thread.
+pub fn thread_exit(code: usize) -> ! {
+    unsafe {
+        sys_call(SysCall::ThreadExit, code, 0);
+    }
+    unreachable!("Exited thread was scheduled");
 }
 
-pub fn uart_close() -> bool {
-    unsafe { sys_call(SysCall::UartClose, 0, 0) != 0 }
+/// Blocks until the thread with the id exited. Returns its exit code.
+pub fn thread_join(thread: usize) -> Result<usize, SysCallError> {
+    unsafe { riscv::from_ret(sys_call(SysCall::ThreadJoin, thread, 0)) }
 }
@@ -0,0 +1,97 @@
+//! Threads sharing the memory of the user program and their thread-local storage.
+
+use crate::sys_call::{thread_create, thread_exit, thread_join, SysCallError};
+
+/// The kernel stores the registers of the thread on its stack, they take 256 bytes.
+const MIN_STACK_SIZE: usize = 1024;
+
+/// A started thread. Joining it returns the value returned by its function.
+pub struct JoinHandle(usize);
+impl JoinHandle {
+    pub fn join(self) -> Result<usize, SysCallError> {
+        thread_join(self.0)
+    }
+    /// Returns the id of the thread.
+    pub fn id(&self) -> usize {
+        self.0
+    }
+}
+
+/// Runs the function with the argument in a new thread on the stack.
+/// The function and its argument are stored at the top of the stack, the kernel places the TLS
+/// block of the thread below them.
+pub fn spawn(
+    stack: &'static mut [u8],
+    func: fn(usize) -> usize,
+    arg: usize,
+) -> Result<JoinHandle, SysCallError> {
+    let top = (stack.as_mut_ptr() as usize + stack.len()) & !0xf;
+    let start = top - 16;
+    if start < stack.as_ptr() as usize + MIN_STACK_SIZE {
+        return Err(SysCallError::InvalidArgument);
+    }
+    unsafe { (start as *mut [usize; 2]).write([func as usize, arg]) };
+    thread_create(thread_start as *const () as usize, start, start).map(JoinHandle)
+}
+
+/// The first function of every thread started by [spawn].
+extern "C" fn thread_start(start: *const [usize; 2]) -> ! {
+    let [func, arg] = unsafe { start.read() };
+    let func: fn(usize) -> usize = unsafe { core::mem::transmute(func) };
+    thread_exit(func(arg))
+}
+
+/// A thread-local static declared with [thread_local!](crate::thread_local).
+pub struct LocalKey<T: 'static> {
+    address: fn() -> *mut T,
+}
+impl<T: 'static> LocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(address: fn() -> *mut T) -> Self {
+        LocalKey { address }
+    }
+    /// Calls the function with the value of the current thread.
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        f(unsafe { &*(self.address)() })
+    }
+}
+
+/// Declares statics of which every thread has its own copy, like `#[thread_local]` statics.
+///
+/// The initial value is a constant stored in the `.tdata` section of the program image, the kernel
+/// copies it into the TLS block of every thread. The values are never dropped.
+///
+/// ```ignore
+/// thread_local! {
+///     static COUNT: Cell<usize> = Cell::new(0);
+/// }
+/// COUNT.with(|count| count.set(count.get() + 1));
+/// ```
+#[macro_export]
+macro_rules! thread_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr; $($rest:tt)*) => {
+        $(#[$attr])*
+        $vis static $name: $crate::thread::LocalKey<$ty> = {
+            #[link_section = ".tdata"]
+            static mut TEMPLATE: $ty = $init;
+            /// Returns the address of the copy in the TLS block `tp` points at.
+            fn address() -> *mut $ty {
+                let address;
+                unsafe {
+                    ::core::arch::asm!(
+                        "lui {0}, %tprel_hi({1})",
+                        "add {0}, {0}, tp, %tprel_add({1})",
+                        "addi {0}, {0}, %tprel_lo({1})",
+                        out(reg) address,
+                        sym TEMPLATE,
+                        options(pure, nomem, nostack),
+                    );
+                }
+                address
+            }
+            $crate::thread::LocalKey::new(address)
+        };
+        $crate::thread_local!($($rest)*);
+    };
+}